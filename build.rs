@@ -21,6 +21,8 @@ fn main() -> Result<()> {
         proto_base_path.join("elf.proto"),
         proto_base_path.join("shred.proto"),
         proto_base_path.join("pack.proto"),
+        proto_base_path.join("block.proto"),
+        proto_base_path.join("batch.proto"),
     ];
 
     protos
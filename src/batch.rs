@@ -0,0 +1,258 @@
+use crate::proto::{BatchContext, BatchEffects};
+use crate::utils::vm::with_sbpf_version;
+use prost::Message;
+use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
+use solana_compute_budget::compute_budget::ComputeBudget;
+use solana_program_runtime::loaded_programs::{ProgramCacheEntry, ProgramRuntimeEnvironment};
+use solana_program_runtime::solana_rbpf::program::SBPFVersion;
+use solana_sdk::feature_set::FeatureSet;
+use solana_sdk::pubkey::Pubkey;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::sync::Arc;
+
+/* Batch variants of the harness entrypoints (`sol_compat_*_batch_v1`).
+
+A batch input is a `BatchContext` holding the harness' encoded contexts. The
+output is a `BatchEffects` listing the encoded effects of each context in the
+same order, along with the indices of the contexts the harness rejected, whose
+effects are left empty.
+
+Setup that only depends on the feature set (feature set conversion, program
+runtime environments, builtin program cache entries) is shared by all the
+contexts of a batch. Banks are seeded with each context's accounts, so the
+transaction harness still builds a bank for every context. */
+
+struct EnvironmentKey {
+    feature_set: FeatureSet,
+    compute_budget: ComputeBudget,
+    deployment: bool,
+    debugging_features: bool,
+    // Set if the environment only enables this SBPF version
+    sbpf_version: Option<SBPFVersion>,
+}
+
+#[derive(Default)]
+struct BatchCache {
+    feature_sets: HashMap<Vec<u64>, FeatureSet>,
+    environments: Vec<(EnvironmentKey, ProgramRuntimeEnvironment)>,
+    builtins: Option<Vec<(Pubkey, Arc<ProgramCacheEntry>)>>,
+    core_bpf_programs: Vec<(Pubkey, ProgramRuntimeEnvironment, Arc<ProgramCacheEntry>)>,
+}
+
+thread_local! {
+    // Only set while executing a batch, so single executions don't retain
+    // anything
+    static BATCH_CACHE: RefCell<Option<BatchCache>> = const { RefCell::new(None) };
+}
+
+pub(crate) unsafe fn execute_batch<C, E>(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+    execute: impl Fn(C) -> Option<E>,
+) -> c_int
+where
+    C: Message + Default,
+    E: Message,
+{
+    if in_ptr.is_null() || in_sz == 0 {
+        return 0;
+    }
+    let in_slice = std::slice::from_raw_parts(in_ptr, in_sz as usize);
+    let batch_context = match BatchContext::decode(in_slice) {
+        Ok(context) => context,
+        Err(_) => return 0,
+    };
+
+    BATCH_CACHE.with(|cache| *cache.borrow_mut() = Some(BatchCache::default()));
+    let mut batch_effects = BatchEffects::default();
+    for (index, context) in batch_context.contexts.iter().enumerate() {
+        match C::decode(context.as_slice()).ok().and_then(&execute) {
+            Some(effects) => batch_effects.effects.push(effects.encode_to_vec()),
+            None => {
+                batch_effects.effects.push(vec![]);
+                batch_effects.failed.push(index as u32);
+            }
+        }
+    }
+    BATCH_CACHE.with(|cache| *cache.borrow_mut() = None);
+
+    let out_slice = std::slice::from_raw_parts_mut(out_ptr, (*out_psz) as usize);
+    let out_vec = batch_effects.encode_to_vec();
+    if out_vec.len() > out_slice.len() {
+        return 0;
+    }
+    out_slice[..out_vec.len()].copy_from_slice(&out_vec);
+    *out_psz = out_vec.len() as u64;
+
+    1
+}
+
+/* Memoizes `load` within a batch. The cache isn't borrowed while loading, so
+`load` may use other memoized helpers. */
+fn memoize<T: Clone>(
+    get: impl FnOnce(&BatchCache) -> Option<T>,
+    load: impl FnOnce() -> T,
+    insert: impl FnOnce(&mut BatchCache, T),
+) -> T {
+    let cached = BATCH_CACHE.with(|cache| cache.borrow().as_ref().and_then(get));
+    if let Some(value) = cached {
+        return value;
+    }
+    let value = load();
+    BATCH_CACHE.with(|cache| {
+        if let Some(cache) = cache.borrow_mut().as_mut() {
+            insert(cache, value.clone());
+        }
+    });
+    value
+}
+
+pub(crate) fn feature_set(features: &[u64], convert: impl FnOnce() -> FeatureSet) -> FeatureSet {
+    memoize(
+        |cache| cache.feature_sets.get(features).cloned(),
+        convert,
+        |cache, feature_set| {
+            cache.feature_sets.insert(features.to_vec(), feature_set);
+        },
+    )
+}
+
+/* Same as `create_program_runtime_environment_v1`. The environment doesn't
+depend on the compute unit limit, which is ignored when looking up the batch
+cache. */
+pub(crate) fn program_runtime_environment_v1(
+    feature_set: &FeatureSet,
+    compute_budget: &ComputeBudget,
+    deployment: bool,
+    debugging_features: bool,
+) -> ProgramRuntimeEnvironment {
+    program_runtime_environment(
+        feature_set,
+        compute_budget,
+        deployment,
+        debugging_features,
+        None,
+        || {
+            Arc::new(
+                create_program_runtime_environment_v1(
+                    feature_set,
+                    compute_budget,
+                    deployment,
+                    debugging_features,
+                )
+                .unwrap(),
+            )
+        },
+    )
+}
+
+/* Same as `program_runtime_environment_v1`, with only `sbpf_version` enabled,
+for the VM harnesses which execute under the fixture's SBPF version */
+pub(crate) fn program_runtime_environment_v1_for_sbpf_version(
+    feature_set: &FeatureSet,
+    compute_budget: &ComputeBudget,
+    deployment: bool,
+    debugging_features: bool,
+    sbpf_version: SBPFVersion,
+) -> ProgramRuntimeEnvironment {
+    program_runtime_environment(
+        feature_set,
+        compute_budget,
+        deployment,
+        debugging_features,
+        Some(sbpf_version),
+        || {
+            let environment = program_runtime_environment_v1(
+                feature_set,
+                compute_budget,
+                deployment,
+                debugging_features,
+            );
+            Arc::new(with_sbpf_version(&environment, sbpf_version))
+        },
+    )
+}
+
+fn program_runtime_environment(
+    feature_set: &FeatureSet,
+    compute_budget: &ComputeBudget,
+    deployment: bool,
+    debugging_features: bool,
+    sbpf_version: Option<SBPFVersion>,
+    load: impl FnOnce() -> ProgramRuntimeEnvironment,
+) -> ProgramRuntimeEnvironment {
+    let compute_budget_key = ComputeBudget {
+        compute_unit_limit: 0,
+        ..*compute_budget
+    };
+    memoize(
+        |cache| {
+            cache
+                .environments
+                .iter()
+                .find(|(key, _)| {
+                    key.feature_set == *feature_set
+                        && key.compute_budget == compute_budget_key
+                        && key.deployment == deployment
+                        && key.debugging_features == debugging_features
+                        && key.sbpf_version == sbpf_version
+                })
+                .map(|(_, environment)| environment.clone())
+        },
+        load,
+        |cache, environment| {
+            let key = EnvironmentKey {
+                feature_set: feature_set.clone(),
+                compute_budget: compute_budget_key,
+                deployment,
+                debugging_features,
+                sbpf_version,
+            };
+            cache.environments.push((key, environment));
+        },
+    )
+}
+
+pub(crate) fn builtin_program_entries(
+    load: impl FnOnce() -> Vec<(Pubkey, Arc<ProgramCacheEntry>)>,
+) -> Vec<(Pubkey, Arc<ProgramCacheEntry>)> {
+    memoize(
+        |cache| cache.builtins.clone(),
+        load,
+        |cache, entries| cache.builtins = Some(entries),
+    )
+}
+
+/* Core BPF programs are loaded under a given environment, so entries are
+keyed by program ID and environment instance */
+pub(crate) fn core_bpf_program_entry(
+    program_id: &Pubkey,
+    environment: &ProgramRuntimeEnvironment,
+    load: impl FnOnce() -> Option<Arc<ProgramCacheEntry>>,
+) -> Option<Arc<ProgramCacheEntry>> {
+    memoize(
+        |cache| {
+            cache
+                .core_bpf_programs
+                .iter()
+                .find(|(key, key_environment, _)| {
+                    key == program_id && Arc::ptr_eq(key_environment, environment)
+                })
+                .map(|(_, _, entry)| Some(entry.clone()))
+        },
+        load,
+        |cache, entry| {
+            if let Some(entry) = entry {
+                // Keeping the environment alive ensures its address isn't
+                // reused by another environment during the batch
+                cache
+                    .core_bpf_programs
+                    .push((*program_id, environment.clone(), entry));
+            }
+        },
+    )
+}
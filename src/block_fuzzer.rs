@@ -0,0 +1,141 @@
+use crate::batch;
+use crate::proto::{AcctState, BlockContext, BlockEffects};
+use crate::txn_fuzzer::{new_child_bank, process_transaction, setup_bank, TxnOptions};
+use prost::Message;
+use solana_program::hash::Hash;
+use solana_program::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::ffi::c_int;
+
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_block_execute_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    if in_ptr.is_null() || in_sz == 0 {
+        return 0;
+    }
+    let in_slice = std::slice::from_raw_parts(in_ptr, in_sz as usize);
+    let block_context = match BlockContext::decode(in_slice) {
+        Ok(context) => context,
+        Err(_) => return 0, // Decode error
+    };
+
+    let block_effects = match execute_block(block_context) {
+        Some(value) => value,
+        None => return 0, // Data format error
+    };
+
+    let out_slice = std::slice::from_raw_parts_mut(out_ptr, (*out_psz) as usize);
+    let out_vec = block_effects.encode_to_vec();
+    if out_vec.len() > out_slice.len() {
+        return 0;
+    }
+
+    out_slice[..out_vec.len()].copy_from_slice(&out_vec);
+    *out_psz = out_vec.len() as u64;
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_block_execute_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_block)
+}
+
+/* Executes an ordered list of transactions, grouped by slot, against a
+single bank lineage. Unlike the txn harness, every transaction is committed,
+so state written by one transaction is visible to the next. Slots must be
+non-decreasing; each new slot is a child of the previous slot's bank. */
+pub fn execute_block(context: BlockContext) -> Option<BlockEffects> {
+    let fd_features = context
+        .epoch_ctx
+        .as_ref()
+        .map(|ctx| ctx.features.clone().unwrap_or_default())
+        .unwrap_or_default();
+
+    let first_slot = context.slots.first()?.slot;
+    let (bank_forks, mut bank) = setup_bank(
+        &fd_features,
+        first_slot,
+        &context.acct_states,
+        context.blockhash_queue,
+    )?;
+
+    /* Keep track of every account the block touches (in first-seen order),
+    so that the final state of each can be reported */
+    let mut seen_accounts = HashSet::<Pubkey>::new();
+    let mut touched_accounts = Vec::<Pubkey>::new();
+    let mut touch = |pubkey: Pubkey| {
+        if seen_accounts.insert(pubkey) {
+            touched_accounts.push(pubkey);
+        }
+    };
+    for account in &context.acct_states {
+        touch(Pubkey::new_from_array(
+            account.address.clone().try_into().ok()?,
+        ));
+    }
+
+    let mut txn_results = Vec::new();
+    for block_slot in &context.slots {
+        if block_slot.slot != bank.slot() {
+            if block_slot.slot < bank.slot() {
+                return None;
+            }
+            bank = new_child_bank(&bank_forks, bank, block_slot.slot);
+        }
+
+        /* Register the slot's blockhash, if any (e.g. to let durable nonces
+        advance across slots) */
+        if !block_slot.blockhash.is_empty() {
+            let blockhash = Hash::new_from_array(block_slot.blockhash.clone().try_into().ok()?);
+            let lamports_per_signature =
+                bank.get_lamports_per_signature_for_blockhash(&bank.last_blockhash());
+            bank.register_recent_blockhash_for_test(&blockhash, lamports_per_signature);
+            bank.update_recent_blockhashes();
+            bank.get_transaction_processor().reset_sysvar_cache();
+            bank.get_transaction_processor()
+                .fill_missing_sysvar_cache_entries(bank.as_ref());
+        }
+
+        for tx in &block_slot.txns {
+            let txn_result = process_transaction(&bank, tx, true, &TxnOptions::default())?;
+            for key in tx.message.iter().flat_map(|message| &message.account_keys) {
+                touch(Pubkey::new_from_array(key.clone().try_into().ok()?));
+            }
+            for account in txn_result
+                .resulting_state
+                .iter()
+                .flat_map(|state| &state.acct_states)
+            {
+                touch(Pubkey::new_from_array(
+                    account.address.clone().try_into().ok()?,
+                ));
+            }
+            txn_results.push(txn_result);
+        }
+    }
+
+    /* Accounts that were closed during the block are reported with their
+    default (zero-lamport) state */
+    let acct_states = touched_accounts
+        .into_iter()
+        .map(|pubkey| {
+            let account = bank.get_account(&pubkey).unwrap_or_default();
+            AcctState::from((pubkey, account))
+        })
+        .collect();
+
+    Some(BlockEffects {
+        txn_results,
+        acct_states,
+    })
+}
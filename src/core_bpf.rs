@@ -0,0 +1,222 @@
+use crate::batch;
+use crate::utils::account_store::AccountStore;
+use lazy_static::lazy_static;
+use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
+use solana_compute_budget::compute_budget::ComputeBudget;
+use solana_program_runtime::loaded_programs::{
+    LoadProgramMetrics, ProgramCacheEntry, ProgramCacheForTxBatch,
+};
+use solana_sdk::account::{Account, AccountSharedData, WritableAccount};
+use solana_sdk::feature_set::FeatureSet;
+use solana_sdk::pubkey::Pubkey;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::c_int;
+use std::sync::{Arc, RwLock};
+
+/* Runtime counterpart of the `load_core_bpf_program!` macro: instead of
+embedding a single ELF at build time, any number of program IDs can be mapped
+to a BPF ELF while the library is loaded. Each mapped program is replaced in
+the program cache by the loaded ELF (under loader v3) whenever the builtins
+are loaded. */
+
+lazy_static! {
+    static ref CORE_BPF_PROGRAMS: RwLock<HashMap<Pubkey, Arc<Vec<u8>>>> =
+        RwLock::new(HashMap::new());
+}
+
+thread_local! {
+    // Set while running `with_builtins`, hides every configured program
+    static BUILTINS_ONLY: Cell<bool> = const { Cell::new(false) };
+}
+
+/* Environment variable read by `sol_compat_init`, as a comma-separated list
+of `<program_id>:<path to ELF>` entries */
+pub const CORE_BPF_PROGRAMS_ENV: &str = "CORE_BPF_PROGRAMS";
+
+fn load_cache_entry(cache: &ProgramCacheForTxBatch, elf: &[u8]) -> Option<Arc<ProgramCacheEntry>> {
+    ProgramCacheEntry::new(
+        &solana_sdk::bpf_loader_upgradeable::id(),
+        cache.environments.program_runtime_v1.clone(),
+        0,
+        0,
+        elf,
+        elf.len(),
+        &mut LoadProgramMetrics::default(),
+    )
+    .ok()
+    .map(Arc::new)
+}
+
+/* Replaces `program_id` with the given ELF. Returns false (and leaves the
+current configuration untouched) if the ELF can't be loaded. */
+pub fn set_core_bpf_program(program_id: Pubkey, elf: Vec<u8>) -> bool {
+    let environment = create_program_runtime_environment_v1(
+        &FeatureSet::all_enabled(),
+        &ComputeBudget::default(),
+        false, /* deployment */
+        false, /* debugging_features */
+    )
+    .unwrap();
+    let mut cache = ProgramCacheForTxBatch::default();
+    cache.environments.program_runtime_v1 = Arc::new(environment);
+    if load_cache_entry(&cache, &elf).is_none() {
+        return false;
+    }
+
+    CORE_BPF_PROGRAMS
+        .write()
+        .unwrap()
+        .insert(program_id, Arc::new(elf));
+    true
+}
+
+/* Restores the builtin version of `program_id` */
+pub fn clear_core_bpf_program(program_id: &Pubkey) {
+    CORE_BPF_PROGRAMS.write().unwrap().remove(program_id);
+}
+
+pub fn is_core_bpf_program(program_id: &Pubkey) -> bool {
+    !BUILTINS_ONLY.with(Cell::get) && CORE_BPF_PROGRAMS.read().unwrap().contains_key(program_id)
+}
+
+/* Runs `f` with the builtin version of every program on the current thread,
+as if no program was configured */
+pub(crate) fn with_builtins<R>(f: impl FnOnce() -> R) -> R {
+    let previous = BUILTINS_ONLY.with(|builtins_only| builtins_only.replace(true));
+    let result = f();
+    BUILTINS_ONLY.with(|builtins_only| builtins_only.set(previous));
+    result
+}
+
+/* Replaces every configured program in the cache with its loaded ELF, and
+removes its ID from the `builtins` set. Returns None if a program fails to
+load under the cache's environment, rather than silently executing its
+builtin version. */
+pub(crate) fn load_core_bpf_programs(
+    cache: &mut ProgramCacheForTxBatch,
+    builtins: &mut HashSet<Pubkey>,
+) -> Option<()> {
+    if BUILTINS_ONLY.with(Cell::get) {
+        return Some(());
+    }
+    for (program_id, elf) in CORE_BPF_PROGRAMS.read().unwrap().iter() {
+        let environment = &cache.environments.program_runtime_v1;
+        let entry = batch::core_bpf_program_entry(program_id, environment, || {
+            load_cache_entry(cache, elf)
+        })?;
+        cache.replenish(*program_id, entry);
+        builtins.remove(program_id);
+    }
+    Some(())
+}
+
+/* `DEFAULT_COMPUTE_UNITS` of the builtin versions of programs. The effects
+of a Core BPF program are reconciled with its builtin's CU deduction. */
+pub(crate) fn builtin_default_compute_units(program_id: &Pubkey) -> Option<u64> {
+    if *program_id == solana_sdk::address_lookup_table::program::id() {
+        Some(solana_address_lookup_table_program::processor::DEFAULT_COMPUTE_UNITS)
+    } else if *program_id == solana_config_program::id() {
+        Some(solana_config_program::config_processor::DEFAULT_COMPUTE_UNITS)
+    } else if *program_id == solana_sdk::compute_budget::id() {
+        Some(solana_compute_budget_program::DEFAULT_COMPUTE_UNITS)
+    } else if *program_id == solana_stake_program::id() {
+        Some(solana_stake_program::stake_instruction::DEFAULT_COMPUTE_UNITS)
+    } else if *program_id == solana_system_program::id() {
+        Some(solana_system_program::system_processor::DEFAULT_COMPUTE_UNITS)
+    } else if *program_id == solana_vote_program::id() {
+        Some(solana_vote_program::vote_processor::DEFAULT_COMPUTE_UNITS)
+    } else {
+        None
+    }
+}
+
+/* Custom errors Core BPF programs throw where their builtin fails a writable
+check with `InstructionError::ReadonlyDataModified`, see `execute_instr` */
+pub(crate) fn is_readonly_custom_error(program_id: &Pubkey, code: u32) -> bool {
+    (*program_id == solana_sdk::address_lookup_table::program::id() && code == 10)
+        || (*program_id == solana_config_program::id() && code == 0)
+}
+
+/* Fixtures provide a Core BPF program's account as a builtin (owned by the
+native loader), but the program runtime expects the account owner to match
+the cache entry. Since the ELF was loaded under loader v3, stub out the
+program account.
+
+Note: Agave does this during transaction account loading.
+https://github.com/anza-xyz/agave/blob/6d74d13749829d463fabccebd8203edf0cf4c500/svm/src/account_loader.rs#L246-L249 */
+pub(crate) fn stub_program_account(account: &mut AccountSharedData) {
+    account.set_owner(solana_sdk::bpf_loader_upgradeable::id());
+    account.set_executable(true);
+}
+
+/* Reads the `CORE_BPF_PROGRAMS` environment variable, if set. Returns an
+error describing the first malformed entry, the entries before it stay
+configured. */
+pub(crate) fn load_core_bpf_programs_from_env() -> Result<(), String> {
+    let Ok(programs) = env::var(CORE_BPF_PROGRAMS_ENV) else {
+        return Ok(());
+    };
+    for entry in programs.split(',').filter(|entry| !entry.is_empty()) {
+        let (program_id, path) = entry
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid {} entry: {}", CORE_BPF_PROGRAMS_ENV, entry))?;
+        let program_id: Pubkey = program_id
+            .parse()
+            .map_err(|_| format!("Invalid program id: {}", program_id))?;
+        let elf =
+            std::fs::read(path).map_err(|err| format!("Failed to read \"{}\": {}", path, err))?;
+        if !set_core_bpf_program(program_id, elf) {
+            return Err(format!(
+                "Failed to load ELF \"{}\" for {}",
+                path, program_id
+            ));
+        }
+        eprintln!(
+            "    [SF_AGAVE]: Overriding builtin program with provided BPF target: {}",
+            program_id
+        );
+    }
+    Ok(())
+}
+
+/* Maps `program_id` (32 bytes) to the ELF at `elf_ptr`. A zero `elf_sz`
+restores the builtin version of the program. Returns 1 on success, 0 if the
+inputs are invalid or the ELF can't be loaded. */
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_set_core_bpf_v1(
+    program_id_ptr: *const u8,
+    elf_ptr: *const u8,
+    elf_sz: u64,
+) -> c_int {
+    if program_id_ptr.is_null() {
+        return 0;
+    }
+    let program_id = Pubkey::new_from_array(
+        std::slice::from_raw_parts(program_id_ptr, 32)
+            .try_into()
+            .unwrap(),
+    );
+    if elf_sz == 0 {
+        clear_core_bpf_program(&program_id);
+        return 1;
+    }
+    if elf_ptr.is_null() {
+        return 0;
+    }
+    let elf = std::slice::from_raw_parts(elf_ptr, elf_sz as usize).to_vec();
+    set_core_bpf_program(program_id, elf) as c_int
+}
+
+/* Returns the fixture's original (builtin) account for a Core BPF program,
+so that effects don't report the stubbed out account */
+pub(crate) fn original_program_account(
+    pubkey: &Pubkey,
+    accounts: &AccountStore,
+) -> Option<(Pubkey, Account)> {
+    if !is_core_bpf_program(pubkey) {
+        return None;
+    }
+    Some((*pubkey, accounts.get(pubkey)?.clone().into()))
+}
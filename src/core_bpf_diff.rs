@@ -0,0 +1,99 @@
+use crate::core_bpf;
+use crate::proto::{self, CoreBpfDiffReport};
+use crate::{execute_instr, InstrContext};
+use prost::Message;
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::ffi::c_int;
+
+/* Differential mode for programs configured via `core_bpf`: executes the
+same instruction through the builtin and through the loaded ELF in the same
+process, and reports where the two diverge.
+
+CUs are not compared. Like any Core BPF program, the BPF version runs with
+the default budget for BPF programs, unless the context doesn't cover the
+builtin's `DEFAULT_COMPUTE_UNITS` (in which case both must exhaust the meter).
+The BPF effects report the builtin's CU deduction. */
+
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_instr_execute_core_bpf_diff_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    if in_ptr.is_null() || in_sz == 0 {
+        return 0;
+    }
+    let in_slice = std::slice::from_raw_parts(in_ptr, in_sz as usize);
+    let instr_context = match proto::InstrContext::decode(in_slice) {
+        Ok(context) => context,
+        Err(_) => return 0,
+    };
+
+    let report = match execute_instr_core_bpf_diff(instr_context) {
+        Some(report) => report,
+        None => return 0,
+    };
+
+    let out_slice = std::slice::from_raw_parts_mut(out_ptr, (*out_psz) as usize);
+    let out_vec = report.encode_to_vec();
+    if out_vec.len() > out_slice.len() {
+        return 0;
+    }
+    out_slice[..out_vec.len()].copy_from_slice(&out_vec);
+    *out_psz = out_vec.len() as u64;
+
+    1
+}
+
+fn find_account<'a>(accounts: &'a [(Pubkey, Account)], pubkey: &Pubkey) -> Option<&'a Account> {
+    accounts
+        .iter()
+        .find(|(key, _)| key == pubkey)
+        .map(|(_, account)| account)
+}
+
+/* Returns None if the context is invalid, targets a program that isn't
+configured as Core BPF, or carries additional instructions */
+pub fn execute_instr_core_bpf_diff(input: proto::InstrContext) -> Option<CoreBpfDiffReport> {
+    if !input.additional_instrs.is_empty() {
+        return None;
+    }
+    let program_id = Pubkey::new_from_array(input.program_id.clone().try_into().ok()?);
+    if !core_bpf::is_core_bpf_program(&program_id) {
+        return None;
+    }
+    core_bpf::builtin_default_compute_units(&program_id)?;
+
+    // The BPF run is reconciled with the builtin by `execute_instr`
+    let builtin_context = InstrContext::try_from(input.clone()).ok()?;
+    let builtin_effects = core_bpf::with_builtins(|| execute_instr(builtin_context))?;
+    let bpf_context = InstrContext::try_from(input).ok()?;
+    let bpf_effects = execute_instr(bpf_context)?;
+
+    // An account reported by only one of the runs is a mismatch as well
+    let mut compared = HashSet::new();
+    let mismatched_accounts = builtin_effects
+        .modified_accounts
+        .iter()
+        .chain(bpf_effects.modified_accounts.iter())
+        .map(|(pubkey, _)| *pubkey)
+        .filter(|pubkey| compared.insert(*pubkey))
+        .filter(|pubkey| {
+            find_account(&builtin_effects.modified_accounts, pubkey)
+                != find_account(&bpf_effects.modified_accounts, pubkey)
+        })
+        .map(|pubkey| pubkey.to_bytes().to_vec())
+        .collect();
+
+    Some(CoreBpfDiffReport {
+        result_mismatch: builtin_effects.result != bpf_effects.result,
+        custom_err_mismatch: builtin_effects.custom_err != bpf_effects.custom_err,
+        mismatched_accounts,
+        return_data_mismatch: builtin_effects.return_data != bpf_effects.return_data,
+        builtin_effects: Some(builtin_effects.into()),
+        bpf_effects: Some(bpf_effects.into()),
+    })
+}
@@ -1,6 +1,6 @@
+use crate::batch;
 use crate::proto::{ElfLoaderCtx, ElfLoaderEffects};
 use prost::Message;
-use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
 use solana_compute_budget::compute_budget::ComputeBudget;
 use solana_program_runtime::solana_rbpf::{ebpf, elf::Executable};
 use solana_sdk::{feature_set::*, pubkey::Pubkey};
@@ -23,21 +23,17 @@ pub fn load_elf(elf_bytes: &[u8], deploy_checks: bool) -> Option<ElfLoaderEffect
         feature_set.activate(feature, 0);
     }
 
-    let program_runtime_environment_v1 = create_program_runtime_environment_v1(
+    let program_runtime_environment_v1 = batch::program_runtime_environment_v1(
         &feature_set,
         &ComputeBudget::default(),
         deploy_checks,
         false,
-    )
-    .unwrap();
+    );
 
     let mut elf_effects = ElfLoaderEffects::default();
 
     // load the elf
-    let elf_exec = match Executable::load(
-        elf_bytes,
-        std::sync::Arc::new(program_runtime_environment_v1),
-    ) {
+    let elf_exec = match Executable::load(elf_bytes, program_runtime_environment_v1) {
         Ok(v) => v,
         Err(_) => return Some(elf_effects),
     };
@@ -92,6 +88,16 @@ pub unsafe extern "C" fn sol_compat_elf_loader_v1(
     1
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_elf_loader_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_elf_loader)
+}
+
 pub fn execute_elf_loader(input: ElfLoaderCtx) -> Option<ElfLoaderEffects> {
     let mut elf_bytes = match input.elf {
         Some(elf) => elf.data,
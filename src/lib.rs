@@ -1,8 +1,15 @@
 #![allow(clippy::missing_safety_doc)]
 
+mod batch;
+pub mod block_fuzzer;
+pub mod core_bpf;
+// The `core-bpf` feature replaces the builtin for the whole build
+#[cfg(not(feature = "core-bpf"))]
+pub mod core_bpf_diff;
 pub mod elf_loader;
 pub mod pack;
 mod shred_parse;
+mod template_bank;
 pub mod txn_fuzzer;
 pub mod utils;
 pub mod vm_cpi_syscall;
@@ -20,19 +27,28 @@ use solana_program_runtime::loaded_programs::ProgramCacheEntry;
 use solana_program_runtime::loaded_programs::ProgramCacheForTxBatch;
 use solana_program_runtime::loaded_programs::ProgramRuntimeEnvironments;
 use solana_program_runtime::sysvar_cache::SysvarCache;
-use solana_sdk::account::{Account, AccountSharedData, ReadableAccount};
+use solana_runtime::bank::builtins::BUILTINS;
+use solana_sdk::account::{Account, AccountSharedData, ReadableAccount, WritableAccount};
 use solana_sdk::clock::Clock;
 use solana_sdk::epoch_schedule::EpochSchedule;
 use solana_sdk::feature_set::*;
 use solana_sdk::instruction::AccountMeta;
-use solana_sdk::instruction::{CompiledInstruction, InstructionError};
+use solana_sdk::instruction::{
+    CompiledInstruction, InstructionError, TRANSACTION_LEVEL_STACK_HEIGHT,
+};
 use solana_sdk::precompiles::{is_precompile, verify_if_precompile};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::rent::Rent;
 use solana_sdk::rent_collector::RentCollector;
 use solana_sdk::stable_layout::stable_instruction::StableInstruction;
 use solana_sdk::stable_layout::stable_vec::StableVec;
-use solana_sdk::sysvar::last_restart_slot;
+use solana_sdk::sysvar;
+use solana_sdk::sysvar::instructions::{
+    construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction,
+};
+use solana_sdk::sysvar::last_restart_slot::{self, LastRestartSlot};
+#[allow(deprecated)]
+use solana_sdk::sysvar::recent_blockhashes::RecentBlockhashes;
 use solana_sdk::sysvar::SysvarId;
 use solana_sdk::transaction_context::{
     IndexOfAccount, InstructionAccount, TransactionAccount, TransactionContext,
@@ -40,6 +56,7 @@ use solana_sdk::transaction_context::{
 use solana_svm::program_loader;
 use solana_timings::ExecuteTimings;
 
+use crate::utils::account_store::AccountStore;
 use crate::utils::err_map::instr_err_to_num;
 use crate::utils::feature_u64;
 use solana_svm::transaction_processing_callback::TransactionProcessingCallback;
@@ -50,11 +67,7 @@ use std::ffi::c_int;
 use std::sync::Arc;
 use thiserror::Error;
 
-#[cfg(feature = "core-bpf")]
-use {
-    solana_sdk::account::WritableAccount, solana_sdk::slot_hashes::SlotHashes,
-    solana_sdk::sysvar::Sysvar,
-};
+use {solana_sdk::slot_hashes::SlotHashes, solana_sdk::sysvar::Sysvar};
 
 // macro to rewrite &[IDENTIFIER, ...] to &[feature_u64(IDENTIFIER::id()), ...]
 #[macro_export]
@@ -299,61 +312,87 @@ pub enum Error {
 
 pub struct InstrContext {
     pub feature_set: FeatureSet,
-    pub accounts: Vec<(Pubkey, Account)>,
+    pub accounts: AccountStore,
     pub instruction: StableInstruction,
+    /// Top-level instructions executed after `instruction`, in order, on the
+    /// same `TransactionContext`
+    pub additional_instructions: Vec<StableInstruction>,
+    /// Whether to report every invocation recorded in the instruction trace
+    pub instr_trace_enabled: bool,
+    /// Whether to report the program logs
+    pub logs_enabled: bool,
+    /// Builtins loaded (or not) regardless of the feature set
+    pub added_builtins: Vec<Pubkey>,
+    pub removed_builtins: Vec<Pubkey>,
     pub cu_avail: u64,
+    /// Value of the Clock sysvar
+    pub clock: Clock,
+    /// Value of the LastRestartSlot sysvar
+    pub last_restart_slot: u64,
     pub rent_collector: RentCollector,
     pub last_blockhash: Hash,
     pub lamports_per_signature: u64,
 }
 
+impl InstrContext {
+    /// Iterates over all top-level instructions, in execution order
+    pub fn instructions(&self) -> impl Iterator<Item = &StableInstruction> {
+        std::iter::once(&self.instruction).chain(self.additional_instructions.iter())
+    }
+}
+
 impl TransactionProcessingCallback for InstrContext {
     fn account_matches_owners(&self, account: &Pubkey, owners: &[Pubkey]) -> Option<usize> {
-        let account_shared_data: Vec<(Pubkey, AccountSharedData)> = self
-            .accounts
-            .iter()
-            .map(|(_pubkey, _account)| (*_pubkey, AccountSharedData::from(_account.clone())))
-            .collect();
-        if let Some(data) = account_shared_data
-            .iter()
-            .find(|(pubkey, _)| *pubkey == *account)
-            .map(|(_, shared_account)| shared_account)
-        {
-            if data.lamports() == 0 {
-                None
-            } else {
-                owners.iter().position(|entry| data.owner() == entry)
-            }
-        } else {
+        let data = self.accounts.get(account)?;
+        if data.lamports() == 0 {
             None
+        } else {
+            owners.iter().position(|entry| data.owner() == entry)
         }
     }
 
     fn get_account_shared_data(&self, pubkey: &Pubkey) -> Option<AccountSharedData> {
-        let account_shared_data: Vec<(Pubkey, AccountSharedData)> = self
-            .accounts
-            .iter()
-            .map(|(pubkey, account)| (*pubkey, AccountSharedData::from(account.clone())))
-            .collect();
-        account_shared_data
-            .iter()
-            .find(|(_pubkey, _)| *_pubkey == *pubkey)
-            .map(|(_, shared_account)| shared_account)
-            .cloned()
+        self.accounts.get(pubkey).cloned()
     }
 }
 
+fn build_instruction(
+    program_id: Vec<u8>,
+    instr_accounts: Vec<proto::InstrAcct>,
+    data: Vec<u8>,
+    accounts: &[TransactionAccount],
+) -> Result<StableInstruction, Error> {
+    let program_id = Pubkey::new_from_array(
+        program_id
+            .try_into()
+            .map_err(|_| Error::InvalidPubkeyBytes)?,
+    );
+
+    let instruction_accounts = instr_accounts
+        .into_iter()
+        .map(|acct| {
+            if acct.index as usize >= accounts.len() {
+                return Err(Error::AccountMissing);
+            }
+            Ok(AccountMeta {
+                pubkey: accounts[acct.index as usize].0,
+                is_signer: acct.is_signer,
+                is_writable: acct.is_writable,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(StableInstruction {
+        accounts: instruction_accounts.into(),
+        data: data.into(),
+        program_id,
+    })
+}
+
 impl TryFrom<proto::InstrContext> for InstrContext {
     type Error = Error;
 
     fn try_from(input: proto::InstrContext) -> Result<Self, Self::Error> {
-        let program_id = Pubkey::new_from_array(
-            input
-                .program_id
-                .try_into()
-                .map_err(|_| Error::InvalidPubkeyBytes)?,
-        );
-
         let feature_set: FeatureSet = input
             .epoch_context
             .as_ref()
@@ -361,45 +400,198 @@ impl TryFrom<proto::InstrContext> for InstrContext {
             .map(|fs| fs.into())
             .unwrap_or_default();
 
-        let accounts: Vec<(Pubkey, Account)> = input
+        let accounts: AccountStore = input
             .accounts
             .into_iter()
-            .map(|acct_state| acct_state.try_into())
-            .collect::<Result<Vec<_>, _>>()?;
+            .map(|acct_state| {
+                let (pubkey, account): (Pubkey, Account) = acct_state.try_into()?;
+                Ok((pubkey, AccountSharedData::from(account)))
+            })
+            .collect::<Result<_, Error>>()?;
 
-        let instruction_accounts = input
-            .instr_accounts
+        let instruction = build_instruction(
+            input.program_id,
+            input.instr_accounts,
+            input.data,
+            &accounts,
+        )?;
+
+        let additional_instructions = input
+            .additional_instrs
             .into_iter()
-            .map(|acct| {
-                if acct.index as usize >= accounts.len() {
-                    return Err(Error::AccountMissing);
-                }
-                Ok(AccountMeta {
-                    pubkey: accounts[acct.index as usize].0,
-                    is_signer: acct.is_signer,
-                    is_writable: acct.is_writable,
-                })
+            .map(|instr| {
+                build_instruction(
+                    instr.program_id,
+                    instr.instr_accounts,
+                    instr.data,
+                    &accounts,
+                )
             })
             .collect::<Result<Vec<_>, _>>()?;
 
-        let instruction = StableInstruction {
-            accounts: instruction_accounts.into(),
-            data: input.data.into(),
-            program_id,
+        // Each slot context field that is set takes precedence over the
+        // matching sysvar account, which takes precedence over the default.
+        // The Clock is taken as a whole from one source: the fixture's Clock
+        // account, unless the slot context sets the slot, in which case the
+        // epoch is derived from the epoch schedule. The default slot is set to
+        // something arbitrary beyond 0. This prevents DelayedVisibility errors
+        // when executing BPF programs.
+        let slot_ctx = input.slot_context.unwrap_or_default();
+        let epoch_schedule = sysvar_account::<EpochSchedule>(&accounts).unwrap_or_default();
+        let clock = match (slot_ctx.slot, sysvar_account::<Clock>(&accounts)) {
+            (None, Some(clock)) => clock,
+            (slot, _) => {
+                let slot = slot.unwrap_or(10);
+                Clock {
+                    slot,
+                    // Fixtures may provide a degenerate epoch schedule
+                    epoch: if epoch_schedule.slots_per_epoch > 0 {
+                        epoch_schedule.get_epoch(slot)
+                    } else {
+                        0
+                    },
+                    ..Clock::default()
+                }
+            }
+        };
+        let last_restart_slot = match slot_ctx.last_restart_slot {
+            0 => sysvar_account::<LastRestartSlot>(&accounts)
+                .map_or(5000, |sysvar| sysvar.last_restart_slot),
+            last_restart_slot => last_restart_slot,
+        };
+        #[allow(deprecated)]
+        let recent_blockhash = sysvar_account::<RecentBlockhashes>(&accounts)
+            .and_then(|recent_blockhashes| recent_blockhashes.last().cloned());
+        let last_blockhash = if slot_ctx.blockhash.is_empty() {
+            recent_blockhash
+                .as_ref()
+                .map_or(Hash::default(), |entry| entry.blockhash)
+        } else {
+            Hash::new_from_array(
+                slot_ctx
+                    .blockhash
+                    .try_into()
+                    .map_err(|_| Error::InvalidHashBytes)?,
+            )
+        };
+        let lamports_per_signature = match slot_ctx.lamports_per_signature {
+            0 => recent_blockhash.map_or(0, |entry| entry.fee_calculator.lamports_per_signature),
+            lamports_per_signature => lamports_per_signature,
+        };
+        let rent_collector = RentCollector {
+            epoch: clock.epoch,
+            epoch_schedule,
+            rent: sysvar_account::<Rent>(&accounts).unwrap_or_default(),
+            ..RentCollector::default()
         };
 
+        let parse_builtins = |program_ids: Vec<Vec<u8>>| {
+            program_ids
+                .into_iter()
+                .map(|program_id| {
+                    let program_id = Pubkey::new_from_array(
+                        program_id
+                            .try_into()
+                            .map_err(|_| Error::InvalidPubkeyBytes)?,
+                    );
+                    // Builtins can only be added if the runtime knows them
+                    if !BUILTINS
+                        .iter()
+                        .any(|builtin| builtin.program_id == program_id)
+                    {
+                        return Err(Error::InvalidFixtureInput);
+                    }
+                    Ok(program_id)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let added_builtins = parse_builtins(input.added_builtins)?;
+        let removed_builtins = parse_builtins(input.removed_builtins)?;
+
         Ok(Self {
             feature_set,
             accounts,
             instruction,
+            additional_instructions,
+            instr_trace_enabled: input.instr_trace_enabled,
+            logs_enabled: input.logs_enabled,
+            added_builtins,
+            removed_builtins,
             cu_avail: input.cu_avail,
-            rent_collector: RentCollector::default(),
-            last_blockhash: Hash::default(),
-            lamports_per_signature: 0,
+            clock,
+            last_restart_slot,
+            rent_collector,
+            last_blockhash,
+            lamports_per_signature,
         })
     }
 }
 
+/* Reads a sysvar from the fixture's accounts, like the sysvar cache does */
+fn sysvar_account<T: Sysvar>(accounts: &AccountStore) -> Option<T> {
+    let account = accounts.get(&T::id())?;
+    if account.lamports() == 0 {
+        return None;
+    }
+    bincode::deserialize(account.data()).ok()
+}
+
+/* Fills the sysvar cache. Clock and LastRestartSlot are the ones resolved from
+the slot context, the other sysvars are read from the fixture's accounts, and
+default values fill in the sysvars the fixture doesn't provide. */
+pub(crate) fn fill_sysvar_cache(sysvar_cache: &mut SysvarCache, input: &InstrContext) {
+    sysvar_cache.fill_missing_entries(|pubkey, callbackback| {
+        if *pubkey == Clock::id() {
+            callbackback(&bincode::serialize(&input.clock).unwrap());
+        }
+        if *pubkey == last_restart_slot::id() {
+            callbackback(&bincode::serialize(&input.last_restart_slot).unwrap());
+        }
+    });
+
+    let alt_program_id = solana_sdk::address_lookup_table::program::id();
+    let is_bpf_alt = input.instruction.program_id == alt_program_id
+        && (cfg!(feature = "core-bpf") || core_bpf::is_core_bpf_program(&alt_program_id));
+
+    sysvar_cache.fill_missing_entries(|pubkey, callbackback| {
+        if let Some(account) = input.accounts.get(pubkey) {
+            if account.lamports() > 0 {
+                // BPF versions of programs, such as Address Lookup Table, rely
+                // on the new `SolGetSysvar` syscall. However, APIs for
+                // querying slot hashes built on top of `SolGetSysvar` are
+                // designed with the assumption that the `SlotHashes` data
+                // stored in the sysvar cache is `SlotHashes::size_of()` in
+                // length.
+                // See https://github.com/anza-xyz/agave/blob/96249691b4b7c873220b27376f271ead38392541/sdk/program/src/sysvar/slot_hashes.rs#L101.
+                //
+                // Fixtures may provide an incorrect sized slot hashes account,
+                // so this step is to rectify it by extending the buffer with
+                // all zeroes before adding it to the sysvar cache.
+                if is_bpf_alt
+                    && pubkey == &SlotHashes::id()
+                    && account.data().len() < SlotHashes::size_of()
+                {
+                    // Extend the data to the right size.
+                    let mut data = vec![0; SlotHashes::size_of()];
+                    data[..account.data().len()].copy_from_slice(account.data());
+                    return callbackback(&data);
+                }
+                callbackback(account.data());
+            }
+        }
+    });
+
+    // Any default values for missing sysvar values should be set here
+    sysvar_cache.fill_missing_entries(|pubkey, callbackback| {
+        if *pubkey == EpochSchedule::id() {
+            callbackback(&bincode::serialize(&EpochSchedule::default()).unwrap());
+        }
+        if *pubkey == Rent::id() {
+            callbackback(&bincode::serialize(&Rent::default()).unwrap());
+        }
+    });
+}
+
 pub fn get_instr_accounts(
     txn_accounts: &[TransactionAccount],
     acct_metas: &StableVec<AccountMeta>,
@@ -435,6 +627,46 @@ pub struct InstrEffects {
     pub modified_accounts: Vec<(Pubkey, Account)>,
     pub cu_avail: u64,
     pub return_data: Vec<u8>,
+    /// Effects of each top-level instruction, only populated when the
+    /// context carries additional instructions
+    pub instr_effects: Vec<InstrEffects>,
+    /// Every invocation recorded in the instruction trace, only populated
+    /// when the context enables it
+    pub instr_trace: Vec<InstrTraceEntry>,
+    /// Program logs (`stable_log` format) recorded while executing, only
+    /// populated when the context enables it
+    pub logs: Vec<String>,
+}
+
+pub struct InstrTraceEntry {
+    pub stack_height: usize,
+    pub program_id: Pubkey,
+    pub instr_accounts: Vec<proto::InstrAcct>,
+    pub data: Vec<u8>,
+    pub cus_consumed: Option<u64>,
+    pub result: Option<InstructionError>,
+}
+
+impl From<InstrTraceEntry> for proto::InstrTraceEntry {
+    fn from(val: InstrTraceEntry) -> Self {
+        proto::InstrTraceEntry {
+            stack_height: val.stack_height as u32,
+            program_id: val.program_id.to_bytes().to_vec(),
+            instr_accounts: val.instr_accounts,
+            data: val.data,
+            cus_consumed: val.cus_consumed,
+            result: val
+                .result
+                .as_ref()
+                .map(instr_err_to_num)
+                .unwrap_or_default(),
+            custom_err: if let Some(InstructionError::Custom(code)) = val.result {
+                code
+            } else {
+                0
+            },
+        }
+    }
 }
 
 impl From<InstrEffects> for proto::InstrEffects {
@@ -461,6 +693,9 @@ impl From<InstrEffects> for proto::InstrEffects {
                 .collect(),
             cu_avail: val.cu_avail,
             return_data: val.return_data,
+            instr_effects: val.instr_effects.into_iter().map(Into::into).collect(),
+            instr_trace: val.instr_trace.into_iter().map(Into::into).collect(),
+            logs: val.logs,
         }
     }
 }
@@ -474,99 +709,55 @@ pub fn execute_instr_proto(input: proto::InstrContext) -> Option<proto::InstrEff
     instr_effects.map(Into::into)
 }
 
-fn load_builtins(cache: &mut ProgramCacheForTxBatch) -> HashSet<Pubkey> {
-    cache.replenish(
-        solana_sdk::address_lookup_table::program::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_address_lookup_table_program::processor::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_sdk::bpf_loader_deprecated::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_bpf_loader_program::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_sdk::bpf_loader::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_bpf_loader_program::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_sdk::bpf_loader_upgradeable::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_bpf_loader_program::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_sdk::compute_budget::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_compute_budget_program::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_config_program::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_config_program::config_processor::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_stake_program::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_stake_program::stake_instruction::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_system_program::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_system_program::system_processor::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_vote_program::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_vote_program::vote_processor::Entrypoint::vm,
-        )),
-    );
-    cache.replenish(
-        solana_zk_sdk::zk_elgamal_proof_program::id(),
-        Arc::new(ProgramCacheEntry::new_builtin(
-            0u64,
-            0usize,
-            solana_zk_elgamal_proof_program::Entrypoint::vm,
-        )),
-    );
+/* Cache entries of every builtin known to the runtime, in `BUILTINS` order */
+fn builtin_program_entries() -> Vec<(Pubkey, Arc<ProgramCacheEntry>)> {
+    BUILTINS
+        .iter()
+        .map(|builtin| {
+            (
+                builtin.program_id,
+                Arc::new(ProgramCacheEntry::new_builtin(
+                    0u64,
+                    0usize,
+                    builtin.entrypoint,
+                )),
+            )
+        })
+        .collect()
+}
 
+/* Loads the builtins enabled by the feature set, like the bank does: a
+builtin is loaded once its `enable_feature_id` is active, and no longer once
+it was migrated to Core BPF. The fixture may then add or remove builtins.
+Returns None if a builtin configured as Core BPF at runtime fails to load. */
+fn load_builtins(
+    cache: &mut ProgramCacheForTxBatch,
+    input: &InstrContext,
+) -> Option<HashSet<Pubkey>> {
     let mut builtins: HashSet<Pubkey> = HashSet::new();
-    builtins.insert(solana_sdk::address_lookup_table::program::id());
-    builtins.insert(solana_sdk::bpf_loader_deprecated::id());
-    builtins.insert(solana_sdk::bpf_loader::id());
-    builtins.insert(solana_sdk::bpf_loader_upgradeable::id());
-    builtins.insert(solana_sdk::compute_budget::id());
-    builtins.insert(solana_config_program::id());
-    builtins.insert(solana_stake_program::id());
-    builtins.insert(solana_system_program::id());
-    builtins.insert(solana_vote_program::id());
-    builtins.insert(solana_zk_sdk::zk_elgamal_proof_program::id());
+    let entries = batch::builtin_program_entries(builtin_program_entries);
+    for (builtin, (program_id, entry)) in BUILTINS.iter().zip(entries) {
+        let enabled = builtin
+            .enable_feature_id
+            .map_or(true, |feature_id| input.feature_set.is_active(&feature_id));
+        let migrated = builtin
+            .core_bpf_migration_config
+            .as_ref()
+            .map_or(false, |config| {
+                input.feature_set.is_active(&config.feature_id)
+            });
+        let included = if input.added_builtins.contains(&program_id) {
+            true
+        } else if input.removed_builtins.contains(&program_id) {
+            false
+        } else {
+            enabled && !migrated
+        };
+        if included {
+            cache.replenish(program_id, entry);
+            builtins.insert(program_id);
+        }
+    }
 
     // If the `CORE_BPF_PROGRAM_ID` and `CORE_BPF_TARGET` environment variables
     // are set, this macro will do the following:
@@ -574,91 +765,68 @@ fn load_builtins(cache: &mut ProgramCacheForTxBatch) -> HashSet<Pubkey> {
     // * Remove that builtin's program ID from the `builtins` set above.
     load_core_bpf_program!();
 
-    builtins
+    // Replace any builtins configured at runtime, see `core_bpf`.
+    core_bpf::load_core_bpf_programs(cache, &mut builtins)?;
+
+    Some(builtins)
 }
 
-fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
-    #[cfg(feature = "core-bpf")]
-    // If the fixture declares `cu_avail` to be less than the builtin version's
-    // `DEFAULT_COMPUTE_UNITS`, the program should fail on compute meter
-    // exhaustion.
-    //
-    // If the builtin version would otherwise _not_ exhuast the CU meter, give
-    // the BPF version the default budget for BPF programs (200k), to avoid any
-    // mismatches from the BPF program exhuasting the meter when the builtin
-    // did not.
-    let compute_budget = {
-        let mut budget = ComputeBudget::default();
-        if input.cu_avail <= CORE_BPF_DEFAULT_COMPUTE_UNITS {
-            budget.compute_unit_limit = 0; // Ensures CU meter exhaustion.
-        }
-        budget
-    };
-    #[cfg(not(feature = "core-bpf"))]
-    let compute_budget = ComputeBudget {
-        compute_unit_limit: input.cu_avail,
-        ..ComputeBudget::default()
-    };
+/* The builtin's `DEFAULT_COMPUTE_UNITS` if `program_id` is replaced by its
+Core BPF version, either at build time (`core-bpf` feature) or at runtime (see
+`core_bpf`). The effects of a Core BPF program are then reconciled with those
+of its builtin version. */
+#[cfg(feature = "core-bpf")]
+fn core_bpf_default_compute_units(_program_id: &Pubkey) -> Option<u64> {
+    Some(CORE_BPF_DEFAULT_COMPUTE_UNITS)
+}
+#[cfg(not(feature = "core-bpf"))]
+fn core_bpf_default_compute_units(program_id: &Pubkey) -> Option<u64> {
+    if !core_bpf::is_core_bpf_program(program_id) {
+        return None;
+    }
+    core_bpf::builtin_default_compute_units(program_id)
+}
 
-    let mut sysvar_cache = SysvarCache::default();
+pub(crate) fn execute_instr(input: InstrContext) -> Option<InstrEffects> {
+    let is_multi_instr = !input.additional_instructions.is_empty();
 
-    // First try populating sysvars from accounts list
-    sysvar_cache.fill_missing_entries(|pubkey, callbackback| {
-        if let Some(account) = input.accounts.iter().find(|(key, _)| key == pubkey) {
-            if account.1.lamports > 0 {
-                #[cfg(feature = "core-bpf")]
-                // BPF versions of programs, such as Address Lookup Table, rely
-                // on the new `SolGetSysvar` syscall. However, APIs for
-                // querying slot hashes built on top of `SolGetSysvar` are
-                // designed with the assumption that the `SlotHashes` data
-                // stored in the sysvar cache is `SlotHashes::size_of()` in
-                // length.
-                // See https://github.com/anza-xyz/agave/blob/96249691b4b7c873220b27376f271ead38392541/sdk/program/src/sysvar/slot_hashes.rs#L101.
-                //
-                // Fixtures may provide an incorrect sized slot hashes account,
-                // so this step is to rectify it by extending the buffer with
-                // all zeroes before adding it to the sysvar cache.
-                if &input.instruction.program_id == &solana_sdk::address_lookup_table::program::id()
-                    && pubkey == &SlotHashes::id()
-                {
-                    if account.1.data.len() < SlotHashes::size_of() {
-                        // Extend the data to the right size.
-                        let mut data = vec![0; SlotHashes::size_of()];
-                        data[..account.1.data.len()].copy_from_slice(&account.1.data);
-                        return callbackback(&data);
-                    }
-                }
-                callbackback(&account.1.data);
+    // Reconciling builtin and BPF effects (see below) assumes a single
+    // top-level instruction targeting the Core BPF program.
+    let core_bpf_compute_units = core_bpf_default_compute_units(&input.instruction.program_id);
+    if is_multi_instr
+        && input
+            .instructions()
+            .any(|instruction| core_bpf_default_compute_units(&instruction.program_id).is_some())
+    {
+        return None;
+    }
+
+    let compute_budget = match core_bpf_compute_units {
+        // If the fixture declares `cu_avail` to be less than the builtin
+        // version's `DEFAULT_COMPUTE_UNITS`, the program should fail on
+        // compute meter exhaustion.
+        //
+        // If the builtin version would otherwise _not_ exhuast the CU meter,
+        // give the BPF version the default budget for BPF programs (200k), to
+        // avoid any mismatches from the BPF program exhuasting the meter when
+        // the builtin did not.
+        Some(default_compute_units) => {
+            let mut budget = ComputeBudget::default();
+            if input.cu_avail <= default_compute_units {
+                budget.compute_unit_limit = 0; // Ensures CU meter exhaustion.
             }
+            budget
         }
-    });
+        None => ComputeBudget {
+            compute_unit_limit: input.cu_avail,
+            ..ComputeBudget::default()
+        },
+    };
 
-    // Any default values for missing sysvar values should be set here
-    sysvar_cache.fill_missing_entries(|pubkey, callbackback| {
-        if *pubkey == Clock::id() {
-            // Set the default clock slot to something arbitrary beyond 0
-            // This prevents DelayedVisibility errors when executing BPF programs
-            let default_clock = Clock {
-                slot: 10,
-                ..Default::default()
-            };
-            let clock_data = bincode::serialize(&default_clock).unwrap();
-            callbackback(&clock_data);
-        }
-        if *pubkey == EpochSchedule::id() {
-            callbackback(&bincode::serialize(&EpochSchedule::default()).unwrap());
-        }
-        if *pubkey == Rent::id() {
-            callbackback(&bincode::serialize(&Rent::default()).unwrap());
-        }
-        if *pubkey == last_restart_slot::id() {
-            let slot_val = 5000_u64;
-            callbackback(&bincode::serialize(&slot_val).unwrap());
-        }
-    });
+    let mut sysvar_cache = SysvarCache::default();
+    fill_sysvar_cache(&mut sysvar_cache, &input);
 
     let clock = sysvar_cache.get_clock().unwrap();
-    let epoch_schedule = sysvar_cache.get_epoch_schedule().unwrap();
 
     // Add checks for rent boundaries
     let rent_ = sysvar_cache.get_rent().unwrap();
@@ -689,15 +857,50 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
             // Note: Agave does this during transaction account loading.
             // https://github.com/anza-xyz/agave/blob/6d74d13749829d463fabccebd8203edf0cf4c500/svm/src/account_loader.rs#L246-L249
             if *pubkey == input.instruction.program_id {
-                let mut stubbed_out_program_account: AccountSharedData = account.clone().into();
+                let mut stubbed_out_program_account = account.clone();
                 stubbed_out_program_account.set_owner(solana_sdk::bpf_loader_upgradeable::id());
                 stubbed_out_program_account.set_executable(true);
                 return (*pubkey, stubbed_out_program_account);
             }
-            (*pubkey, AccountSharedData::from(account.clone()))
+            if core_bpf::is_core_bpf_program(pubkey) {
+                let mut stubbed_out_program_account = account.clone();
+                core_bpf::stub_program_account(&mut stubbed_out_program_account);
+                return (*pubkey, stubbed_out_program_account);
+            }
+            (*pubkey, account.clone())
         })
         .for_each(|x| transaction_accounts.push(x));
 
+    // With additional instructions, the instructions sysvar is built from the
+    // instruction list, like the account loader does for a transaction.
+    // Single-instruction fixtures provide the sysvar account as-is.
+    if is_multi_instr {
+        let instructions = input
+            .instructions()
+            .map(|instruction| BorrowedInstruction {
+                program_id: &instruction.program_id,
+                accounts: instruction
+                    .accounts
+                    .iter()
+                    .map(|meta| BorrowedAccountMeta {
+                        pubkey: &meta.pubkey,
+                        is_signer: meta.is_signer,
+                        is_writable: meta.is_writable,
+                    })
+                    .collect(),
+                data: &instruction.data,
+            })
+            .collect::<Vec<_>>();
+        if let Some((_, sysvar_account)) = transaction_accounts
+            .iter_mut()
+            .find(|(pubkey, _)| *pubkey == sysvar::instructions::id())
+        {
+            sysvar_account.set_data_from_slice(&construct_instructions_data(&instructions));
+            sysvar_account.set_owner(sysvar::id());
+        }
+    }
+
+    #[cfg(feature = "core-bpf")]
     let program_idx = transaction_accounts
         .iter()
         .position(|(pubkey, _)| *pubkey == input.instruction.program_id)?;
@@ -713,48 +916,37 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
     let mut program_cache_for_tx_batch = ProgramCacheForTxBatch::default();
     program_cache_for_tx_batch.set_slot_for_tests(clock.slot);
 
-    let program_runtime_environment_v1 =
-        solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1(
-            &input.feature_set,
-            &compute_budget,
-            false, /* deployment */
-            false, /* debugging_features */
-        )
-        .unwrap();
+    let program_runtime_environment_v1 = batch::program_runtime_environment_v1(
+        &input.feature_set,
+        &compute_budget,
+        false, /* deployment */
+        false, /* debugging_features */
+    );
     let environments = ProgramRuntimeEnvironments {
-        program_runtime_v1: Arc::new(program_runtime_environment_v1),
+        program_runtime_v1: program_runtime_environment_v1,
         ..ProgramRuntimeEnvironments::default()
     };
     program_cache_for_tx_batch.environments = environments.clone();
     program_cache_for_tx_batch.upcoming_environments = Some(environments.clone());
 
-    let loaded_builtins = load_builtins(&mut program_cache_for_tx_batch);
+    let loaded_builtins = load_builtins(&mut program_cache_for_tx_batch, &input)?;
 
     // Skip if the program account is a native program and is not owned by the native loader
     // (Would call the owner instead)
-    if loaded_builtins.contains(&transaction_accounts[program_idx].0)
-        && transaction_accounts[program_idx].1.owner() != &solana_sdk::native_loader::id()
-    {
-        return None;
+    for instruction in input.instructions() {
+        let (program_key, program_account) = transaction_accounts
+            .iter()
+            .find(|(pubkey, _)| *pubkey == instruction.program_id)?;
+        if loaded_builtins.contains(program_key)
+            && program_account.owner() != &solana_sdk::native_loader::id()
+        {
+            return None;
+        }
     }
 
-    #[allow(deprecated)]
-    let (blockhash, lamports_per_signature) = sysvar_cache
-        .get_recent_blockhashes()
-        .ok()
-        .and_then(|x| (*x).last().cloned())
-        .map(|x| (x.blockhash, x.fee_calculator.lamports_per_signature))
-        .unwrap_or_default();
-
-    input.last_blockhash = blockhash;
-    input.lamports_per_signature = lamports_per_signature;
-    input.rent_collector.epoch = clock.epoch;
-    input.rent_collector.epoch_schedule = (*epoch_schedule).clone();
-    input.rent_collector.rent = (*rent_).clone();
-
     let mut newly_loaded_programs = HashSet::<Pubkey>::new();
 
-    for acc in &input.accounts {
+    for acc in input.accounts.iter() {
         #[cfg(feature = "core-bpf")]
         // The Core BPF program's ELF has already been added to the cache.
         // Its transaction account was stubbed out, so it can't be loaded via
@@ -769,12 +961,12 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
             return None;
         }
 
-        if acc.1.executable && program_cache_for_tx_batch.find(&acc.0).is_none() {
+        if acc.1.executable() && program_cache_for_tx_batch.find(&acc.0).is_none() {
             // load_program_with_pubkey expects the owner to be one of the bpf loader
-            if !solana_sdk::loader_v4::check_id(&acc.1.owner)
-                && !solana_sdk::bpf_loader_deprecated::check_id(&acc.1.owner)
-                && !solana_sdk::bpf_loader::check_id(&acc.1.owner)
-                && !solana_sdk::bpf_loader_upgradeable::check_id(&acc.1.owner)
+            if !solana_sdk::loader_v4::check_id(acc.1.owner())
+                && !solana_sdk::bpf_loader_deprecated::check_id(acc.1.owner())
+                && !solana_sdk::bpf_loader::check_id(acc.1.owner())
+                && !solana_sdk::bpf_loader_upgradeable::check_id(acc.1.owner())
             {
                 continue;
             }
@@ -802,11 +994,11 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
 
     let log_collector = LogCollector::new_ref();
     let env_config = EnvironmentConfig::new(
-        blockhash,
+        input.last_blockhash,
         None,
         None,
         Arc::new(input.feature_set.clone()),
-        lamports_per_signature,
+        input.lamports_per_signature,
         &sysvar_cache,
     );
     let mut invoke_context = InvokeContext::new(
@@ -817,15 +1009,10 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
         compute_budget,
     );
 
-    let program_indices = &[program_idx as u16];
-
     let mut compute_units_consumed = 0u64;
 
     let mut timings = ExecuteTimings::default();
 
-    let instruction_accounts =
-        get_instr_accounts(&transaction_accounts, &input.instruction.accounts);
-
     // Precompiles (ed25519, secp256k1)
     // Precompiles are programs that run without the VM and without loading any account.
     // They allow to verify signatures, either ed25519 or Ethereum-like secp256k1
@@ -846,12 +1033,13 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
     //
     // Note: while this test covers the functionality of the precompile, it doesn't
     // cover the fact that the precompile can access data from other instructions.
-    // This will be covered in separated tests.
+    // Contexts with additional instructions verify precompiles against all
+    // instructions below instead.
     let program_id = &input.instruction.program_id;
     let is_precompile = is_precompile(program_id, |id| {
         invoke_context.environment_config.feature_set.is_active(id)
     });
-    if is_precompile {
+    if is_precompile && !is_multi_instr {
         let compiled_instruction = CompiledInstruction {
             program_id_index: 0,
             accounts: vec![],
@@ -876,49 +1064,153 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
             modified_accounts: vec![],
             cu_avail: input.cu_avail,
             return_data: vec![],
+            instr_effects: vec![],
+            instr_trace: vec![],
+            logs: vec![],
         });
     }
 
-    let result = invoke_context.process_instruction(
-        &input.instruction.data,
-        &instruction_accounts,
-        program_indices,
-        &mut compute_units_consumed,
-        &mut timings,
-    );
+    // Top-level instructions are processed in order on the same transaction
+    // context and instruction trace, stopping at the first failure, like in
+    // a transaction
+    let compiled_instructions = input
+        .instructions()
+        .map(|instruction| CompiledInstruction {
+            program_id_index: 0,
+            accounts: vec![],
+            data: instruction.data.to_vec(),
+        })
+        .collect::<Vec<_>>();
+    let mut instr_effects = Vec::<InstrEffects>::new();
+    let mut top_level_cus = Vec::<(usize, u64)>::new();
+    let mut traced_error = None;
+    let mut result = Ok(());
+    for (instruction_index, instruction) in input.instructions().enumerate() {
+        let first_log = log_collector.borrow().get_recorded_content().len();
+        let trace_index = invoke_context
+            .transaction_context
+            .get_instruction_trace_length();
+        let mut instr_compute_units_consumed = 0u64;
+        let instruction_is_precompile =
+            solana_sdk::precompiles::is_precompile(&instruction.program_id, |id| {
+                invoke_context.environment_config.feature_set.is_active(id)
+            });
+        result = if instruction_is_precompile {
+            verify_if_precompile(
+                &instruction.program_id,
+                &compiled_instructions[instruction_index],
+                &compiled_instructions,
+                &invoke_context.environment_config.feature_set,
+            )
+            .map_err(|_| InstructionError::GenericError)
+        } else {
+            if is_multi_instr {
+                if let Some(index) = invoke_context
+                    .transaction_context
+                    .find_index_of_account(&sysvar::instructions::id())
+                {
+                    let mut sysvar_account = invoke_context
+                        .transaction_context
+                        .get_account_at_index(index)
+                        .ok()?
+                        .borrow_mut();
+                    store_current_index(
+                        sysvar_account.data_as_mut_slice(),
+                        instruction_index as u16,
+                    );
+                }
+            }
+            let program_idx = transaction_accounts
+                .iter()
+                .position(|(pubkey, _)| *pubkey == instruction.program_id)?;
+            let instruction_accounts =
+                get_instr_accounts(&transaction_accounts, &instruction.accounts);
+            invoke_context.process_instruction(
+                &instruction.data,
+                &instruction_accounts,
+                &[program_idx as IndexOfAccount],
+                &mut instr_compute_units_consumed,
+                &mut timings,
+            )
+        };
+        compute_units_consumed += instr_compute_units_consumed;
+        if !instruction_is_precompile {
+            top_level_cus.push((trace_index, instr_compute_units_consumed));
+        }
 
-    #[cfg(feature = "core-bpf")]
-    // To keep alignment with a builtin run, deduct only the CUs the builtin
-    // version would have consumed, so the fixture realizes the same CU
-    // deduction across both BPF and builtin in its effects.
-    let cu_avail = input
-        .cu_avail
-        .saturating_sub(CORE_BPF_DEFAULT_COMPUTE_UNITS);
-    #[cfg(not(feature = "core-bpf"))]
-    let cu_avail = input.cu_avail - compute_units_consumed;
+        if is_multi_instr {
+            instr_effects.push(InstrEffects {
+                custom_err: if let Err(InstructionError::Custom(code)) = result {
+                    Some(code)
+                } else {
+                    None
+                },
+                result: result.clone().err(),
+                modified_accounts: snapshot_accounts(
+                    invoke_context.transaction_context,
+                    &transaction_accounts,
+                    &input.accounts,
+                ),
+                cu_avail: input.cu_avail.saturating_sub(compute_units_consumed),
+                return_data: invoke_context
+                    .transaction_context
+                    .get_return_data()
+                    .1
+                    .to_vec(),
+                instr_effects: vec![],
+                instr_trace: vec![],
+                logs: if input.logs_enabled {
+                    log_collector.borrow().get_recorded_content()[first_log..].to_vec()
+                } else {
+                    vec![]
+                },
+            });
+        }
+        if let Err(err) = &result {
+            if !instruction_is_precompile {
+                traced_error = Some((trace_index, err.clone()));
+            }
+            break;
+        }
+    }
+
+    let cu_avail = match core_bpf_compute_units {
+        // To keep alignment with a builtin run, deduct only the CUs the
+        // builtin version would have consumed, so the fixture realizes the
+        // same CU deduction across both BPF and builtin in its effects.
+        Some(default_compute_units) => input.cu_avail.saturating_sub(default_compute_units),
+        None => input.cu_avail - compute_units_consumed,
+    };
 
     let return_data = transaction_context.get_return_data().1.to_vec();
 
+    let logs = log_collector.borrow().get_recorded_content().to_vec();
+
+    let instr_trace = if input.instr_trace_enabled {
+        collect_instr_trace(&transaction_context, &logs, &top_level_cus, traced_error)
+    } else {
+        vec![]
+    };
+
+    let logs = if input.logs_enabled { logs } else { vec![] };
+
     Some(InstrEffects {
-        custom_err: if let Err(InstructionError::Custom(code)) = result {
-            #[cfg(feature = "core-bpf")]
+        custom_err: match result {
             // See comment below under `result` for special-casing of custom
             // errors for Core BPF programs.
-            if program_id == &solana_sdk::address_lookup_table::program::id() && code == 10 {
-                None
-            } else if program_id == &solana_sdk::config::program::id() && code == 0 {
+            Err(InstructionError::Custom(code))
+                if core_bpf_compute_units.is_some()
+                    && core_bpf::is_readonly_custom_error(program_id, code) =>
+            {
                 None
-            } else {
-                Some(code)
             }
-            #[cfg(not(feature = "core-bpf"))]
-            Some(code)
-        } else {
-            None
+            Err(InstructionError::Custom(code)) => Some(code),
+            _ => None,
         },
-        #[allow(clippy::map_identity)]
         result: result.err().map(|err| {
-            #[cfg(feature = "core-bpf")]
+            let Some(default_compute_units) = core_bpf_compute_units else {
+                return err;
+            };
             // Some errors don't directly map between builtins and their BPF
             // versions.
             //
@@ -938,12 +1230,11 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
             // Therefore, some errors require reconciliation when testing a BPF
             // program against its builtin implementation.
             if err == InstructionError::ProgramFailedToComplete
-                && (input.cu_avail <= CORE_BPF_DEFAULT_COMPUTE_UNITS
+                && (input.cu_avail <= default_compute_units
                     || compute_units_consumed >= input.cu_avail)
             {
                 return InstructionError::ComputationalBudgetExceeded;
             }
-            #[cfg(feature = "core-bpf")]
             // Another such error case arises when a program performs a write
             // to an account, but the data it writes is the exact same data
             // that's currently stored in the account state.
@@ -962,23 +1253,13 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
             // errors when `is_writable` checks fail. These errors are
             // special-cased below to avoid fixture mismatches.
             match err {
-                InstructionError::Custom(code) => {
-                    if program_id == &solana_sdk::address_lookup_table::program::id() {
-                        // Special-cased custom error codes for the ALT program.
-                        if code == 10 {
-                            return InstructionError::ReadonlyDataModified;
-                        }
-                    }
-                    if program_id == &solana_sdk::config::program::id() {
-                        // Special-cased custom error codes for the Config program.
-                        if code == 0 {
-                            return InstructionError::ReadonlyDataModified;
-                        }
-                    }
+                InstructionError::Custom(code)
+                    if core_bpf::is_readonly_custom_error(program_id, code) =>
+                {
+                    InstructionError::ReadonlyDataModified
                 }
-                _ => {}
+                _ => err,
             }
-            err
         }),
         modified_accounts: transaction_context
             .deconstruct_without_keys()
@@ -996,22 +1277,183 @@ fn execute_instr(mut input: InstrContext) -> Option<InstrEffects> {
                 // We need to swap back in the original here to avoid a
                 // mismatch.
                 if index == program_idx {
-                    if let Some(program_account) = input
-                        .accounts
-                        .iter()
-                        .find(|(pubkey, _)| *pubkey == input.instruction.program_id)
+                    if let Some(program_account) = input.accounts.get(&input.instruction.program_id)
                     {
-                        return (program_account.0, program_account.1.clone());
+                        return (input.instruction.program_id, program_account.clone().into());
                     }
                 }
+                if let Some(program_account) = core_bpf::original_program_account(
+                    &transaction_accounts[index].0,
+                    &input.accounts,
+                ) {
+                    return program_account;
+                }
                 (transaction_accounts[index].0, data.into())
             })
             .collect(),
         cu_avail,
         return_data,
+        instr_effects,
+        instr_trace,
+        logs,
     })
 }
 
+/* Builds the instruction trace recorded by the transaction context.
+
+The transaction context records neither the CUs nor the result of each
+invocation, so:
+- CUs of top-level instructions are the ones `process_instruction` reports,
+  given with the trace index each top-level instruction was recorded at.
+  CUs of nested invocations are taken from the "consumed" lines of the
+  program log, and left unset when none was logged (e.g. builtins). Each
+  "invoke" line is checked against the program ID and stack height of its
+  trace entry, and the log is no longer used from the first mismatch on (e.g.
+  once the log collector truncates).
+- Results are only reported for top-level instructions. The error is set on
+  the entry at the trace index of the failed instruction. */
+fn collect_instr_trace(
+    transaction_context: &TransactionContext,
+    logs: &[String],
+    top_level_cus: &[(usize, u64)],
+    error: Option<(usize, InstructionError)>,
+) -> Vec<InstrTraceEntry> {
+    let mut instr_trace = Vec::new();
+    for index in 0..transaction_context.get_instruction_trace_length() {
+        let Ok(instruction_context) =
+            transaction_context.get_instruction_context_at_index_in_trace(index)
+        else {
+            break;
+        };
+        let instr_accounts = (0..instruction_context.get_number_of_instruction_accounts())
+            .filter_map(|i| {
+                Some(proto::InstrAcct {
+                    index: instruction_context
+                        .get_index_of_instruction_account_in_transaction(i)
+                        .ok()? as u32,
+                    is_signer: instruction_context.is_instruction_account_signer(i).ok()?,
+                    is_writable: instruction_context
+                        .is_instruction_account_writable(i)
+                        .ok()?,
+                })
+            })
+            .collect();
+        instr_trace.push(InstrTraceEntry {
+            stack_height: instruction_context.get_stack_height(),
+            program_id: instruction_context
+                .get_last_program_key(transaction_context)
+                .copied()
+                .unwrap_or_default(),
+            instr_accounts,
+            data: instruction_context.get_instruction_data().to_vec(),
+            cus_consumed: None,
+            result: None,
+        });
+    }
+
+    // Invocations are logged in the same order they are recorded in the trace
+    let mut logged_invocations = logged_invocations(logs).into_iter();
+    for entry in instr_trace.iter_mut() {
+        let Some(logged) = logged_invocations.next() else {
+            break;
+        };
+        if logged.program_id != entry.program_id || logged.stack_height != entry.stack_height {
+            break;
+        }
+        entry.cus_consumed = logged.compute_units;
+    }
+
+    for (index, compute_units) in top_level_cus {
+        if let Some(entry) = instr_trace.get_mut(*index) {
+            entry.cus_consumed = Some(*compute_units);
+        }
+    }
+    if let Some((index, error)) = error {
+        if let Some(entry) = instr_trace.get_mut(index) {
+            entry.result = Some(error);
+        }
+    }
+
+    instr_trace
+}
+
+struct LoggedInvocation {
+    program_id: Pubkey,
+    stack_height: usize,
+    /// CUs reported in the "consumed" line, if any
+    compute_units: Option<u64>,
+}
+
+/* Returns what the program log reports for each of its "invoke" lines */
+fn logged_invocations(logs: &[String]) -> Vec<LoggedInvocation> {
+    let mut invocations = Vec::<LoggedInvocation>::new();
+    let mut invoke_stack = Vec::<usize>::new();
+    for line in logs {
+        let tokens: Vec<&str> = line.split(' ').collect();
+        // Only consider `Program <program_id> ...` lines, not `Program log: ...`
+        if tokens.len() < 2 || tokens[0] != "Program" {
+            continue;
+        }
+        let Ok(program_id) = tokens[1].parse::<Pubkey>() else {
+            continue;
+        };
+        match tokens.get(2) {
+            Some(&"invoke") => {
+                let Some(stack_height) = tokens
+                    .get(3)
+                    .and_then(|n| n.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+                else {
+                    break;
+                };
+                invoke_stack.push(invocations.len());
+                invocations.push(LoggedInvocation {
+                    program_id,
+                    stack_height,
+                    compute_units: None,
+                });
+            }
+            Some(&"success") | Some(&"failed:") => {
+                invoke_stack.pop();
+            }
+            Some(&"consumed") => {
+                if let (Some(&index), Some(consumed)) = (
+                    invoke_stack.last(),
+                    tokens.get(3).and_then(|n| n.parse::<u64>().ok()),
+                ) {
+                    invocations[index].compute_units = Some(consumed);
+                }
+            }
+            _ => {}
+        }
+    }
+    invocations
+}
+
+/* Copies out the current state of every transaction account */
+fn snapshot_accounts(
+    transaction_context: &TransactionContext,
+    transaction_accounts: &[TransactionAccount],
+    input_accounts: &AccountStore,
+) -> Vec<(Pubkey, Account)> {
+    transaction_accounts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (pubkey, _))| {
+            if let Some(program_account) =
+                core_bpf::original_program_account(pubkey, input_accounts)
+            {
+                return Some(program_account);
+            }
+            let account = transaction_context
+                .get_account_at_index(index as IndexOfAccount)
+                .ok()?
+                .borrow()
+                .clone();
+            Some((*pubkey, account.into()))
+        })
+        .collect()
+}
+
 impl TryFrom<proto::AcctState> for (Pubkey, Account) {
     type Error = Error;
 
@@ -1042,10 +1484,17 @@ impl TryFrom<proto::AcctState> for (Pubkey, Account) {
     }
 }
 
+/* Returns 1 on success, 0 if the Core BPF programs configured through the
+environment can't be loaded */
 #[no_mangle]
-pub unsafe extern "C" fn sol_compat_init(_log_level: i32) {
+pub unsafe extern "C" fn sol_compat_init(_log_level: i32) -> c_int {
     env::set_var("SOLANA_RAYON_THREADS", "1");
     env::set_var("RAYON_NUM_THREADS", "1");
+    if let Err(err) = core_bpf::load_core_bpf_programs_from_env() {
+        eprintln!("    [SF_AGAVE]: {}", err);
+        return 0;
+    }
+    1
 }
 
 #[repr(C)]
@@ -1117,10 +1566,80 @@ pub unsafe extern "C" fn sol_compat_instr_execute_v1(
     1
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_instr_execute_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_instr_proto)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /* A transfer of `lamports` from account 1 to account 2 through the
+    system program, which is also in the fixture's accounts. */
+    fn system_transfer_context(lamports: u64) -> proto::InstrContext {
+        let mut data = vec![0x02, 0x00, 0x00, 0x00];
+        data.extend_from_slice(&lamports.to_le_bytes());
+        proto::InstrContext {
+            program_id: vec![0u8; 32],
+            accounts: vec![
+                proto::AcctState {
+                    address: vec![1u8; 32],
+                    owner: vec![0u8; 32],
+                    lamports: 1000,
+                    data: vec![],
+                    executable: false,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+                proto::AcctState {
+                    address: vec![2u8; 32],
+                    owner: vec![0u8; 32],
+                    lamports: 0,
+                    data: vec![],
+                    executable: false,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+                proto::AcctState {
+                    address: vec![0u8; 32],
+                    owner: solana_sdk::native_loader::id().to_bytes().to_vec(),
+                    lamports: 10000000,
+                    data: b"Solana Program".to_vec(),
+                    executable: true,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+            ],
+            instr_accounts: vec![
+                proto::InstrAcct {
+                    index: 0,
+                    is_signer: true,
+                    is_writable: true,
+                },
+                proto::InstrAcct {
+                    index: 1,
+                    is_signer: false,
+                    is_writable: true,
+                },
+            ],
+            data,
+            cu_avail: 10000u64,
+            epoch_context: None,
+            slot_context: None,
+            additional_instrs: vec![],
+            instr_trace_enabled: false,
+            logs_enabled: false,
+            added_builtins: vec![],
+            removed_builtins: vec![],
+        }
+    }
+
     #[test]
     fn test_system_program_exec() {
         let native_loader_id = solana_sdk::native_loader::id().to_bytes().to_vec();
@@ -1177,6 +1696,11 @@ mod tests {
             cu_avail: 10000u64,
             epoch_context: None,
             slot_context: None,
+            additional_instrs: vec![],
+            instr_trace_enabled: false,
+            logs_enabled: false,
+            added_builtins: vec![],
+            removed_builtins: vec![],
         };
         let output = execute_instr_proto(input);
         assert_eq!(
@@ -1215,7 +1739,427 @@ mod tests {
                 ],
                 cu_avail: 9850u64,
                 return_data: vec![],
+                instr_effects: vec![],
+                instr_trace: vec![],
+                logs: vec![],
             })
         );
     }
+
+    #[test]
+    fn test_system_program_multi_instr_exec() {
+        // Two transfers, where the second one observes the first one
+        let second = system_transfer_context(2);
+        let input = proto::InstrContext {
+            additional_instrs: vec![proto::InstrInvocation {
+                program_id: second.program_id,
+                instr_accounts: second.instr_accounts,
+                data: second.data,
+            }],
+            logs_enabled: true,
+            ..system_transfer_context(1)
+        };
+        let output = execute_instr_proto(input).unwrap();
+        assert_eq!(output.result, 0);
+        assert_eq!(output.cu_avail, 9700u64);
+        assert_eq!(output.modified_accounts[1].lamports, 3);
+
+        assert_eq!(output.instr_effects.len(), 2);
+        assert_eq!(output.instr_effects[0].cu_avail, 9850u64);
+        assert_eq!(output.instr_effects[0].modified_accounts[1].lamports, 1);
+        assert_eq!(output.instr_effects[1].cu_avail, 9700u64);
+        assert_eq!(output.instr_effects[1].modified_accounts[1].lamports, 3);
+
+        assert_eq!(output.logs.len(), 4);
+        assert_eq!(output.instr_effects[0].logs, output.logs[..2]);
+        assert_eq!(output.instr_effects[1].logs, output.logs[2..]);
+    }
+
+    #[test]
+    fn test_system_program_instr_trace() {
+        let input = proto::InstrContext {
+            instr_trace_enabled: true,
+            ..system_transfer_context(1)
+        };
+        let data = input.data.clone();
+        let output = execute_instr_proto(input).unwrap();
+        assert_eq!(output.result, 0);
+        assert_eq!(output.instr_trace.len(), 1);
+
+        let entry = &output.instr_trace[0];
+        assert_eq!(entry.stack_height, 1);
+        assert_eq!(entry.program_id, vec![0u8; 32]);
+        assert_eq!(entry.instr_accounts.len(), 2);
+        assert_eq!(entry.data, data);
+        assert_eq!(entry.cus_consumed, Some(150));
+        assert_eq!(entry.result, 0);
+    }
+
+    #[test]
+    fn test_instr_trace_cpi_before_failure() {
+        use solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+
+        // ExtendProgram pays for the extension through a system program CPI,
+        // then fails to redeploy the (invalid) ELF
+        let native_loader_id = solana_sdk::native_loader::id().to_bytes().to_vec();
+        let loader_id = bpf_loader_upgradeable::id().to_bytes().to_vec();
+        let programdata_address = Pubkey::new_unique();
+        let mut programdata = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: Some(Pubkey::new_unique()),
+        })
+        .unwrap();
+        programdata.resize(
+            UpgradeableLoaderState::size_of_programdata_metadata() + 16,
+            0,
+        );
+        let program = bincode::serialize(&UpgradeableLoaderState::Program {
+            programdata_address,
+        })
+        .unwrap();
+        let mut data = 6u32.to_le_bytes().to_vec(); // ExtendProgram
+        data.extend_from_slice(&16u32.to_le_bytes());
+
+        let input = proto::InstrContext {
+            program_id: loader_id.clone(),
+            accounts: vec![
+                proto::AcctState {
+                    address: programdata_address.to_bytes().to_vec(),
+                    owner: loader_id.clone(),
+                    lamports: 0,
+                    data: programdata,
+                    executable: false,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+                proto::AcctState {
+                    address: vec![1u8; 32],
+                    owner: loader_id.clone(),
+                    lamports: 10000000,
+                    data: program,
+                    executable: false,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+                proto::AcctState {
+                    address: vec![0u8; 32],
+                    owner: native_loader_id.clone(),
+                    lamports: 10000000,
+                    data: b"Solana Program".to_vec(),
+                    executable: true,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+                proto::AcctState {
+                    address: vec![2u8; 32],
+                    owner: vec![0u8; 32],
+                    lamports: 1000000000,
+                    data: vec![],
+                    executable: false,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+                proto::AcctState {
+                    address: loader_id,
+                    owner: native_loader_id,
+                    lamports: 10000000,
+                    data: b"Solana Program".to_vec(),
+                    executable: true,
+                    rent_epoch: 0,
+                    seed_addr: None,
+                },
+            ],
+            instr_accounts: vec![
+                proto::InstrAcct {
+                    index: 0,
+                    is_signer: false,
+                    is_writable: true,
+                },
+                proto::InstrAcct {
+                    index: 1,
+                    is_signer: false,
+                    is_writable: true,
+                },
+                proto::InstrAcct {
+                    index: 2,
+                    is_signer: false,
+                    is_writable: false,
+                },
+                proto::InstrAcct {
+                    index: 3,
+                    is_signer: true,
+                    is_writable: true,
+                },
+            ],
+            data,
+            cu_avail: 1000000u64,
+            epoch_context: None,
+            slot_context: None,
+            additional_instrs: vec![],
+            instr_trace_enabled: true,
+            logs_enabled: false,
+            added_builtins: vec![],
+            removed_builtins: vec![],
+        };
+        let output = execute_instr_proto(input).unwrap();
+        assert_ne!(output.result, 0);
+        assert_eq!(output.instr_trace.len(), 2);
+
+        // The error is set on the failed top-level instruction only. The system
+        // program CPI logs no "consumed" line, so its CUs are unset.
+        let caller = &output.instr_trace[0];
+        assert_eq!(caller.stack_height, 1);
+        assert_eq!(caller.result, output.result);
+        assert!(caller.cus_consumed.is_some());
+        let cpi = &output.instr_trace[1];
+        assert_eq!(cpi.stack_height, 2);
+        assert_eq!(cpi.program_id, vec![0u8; 32]);
+        assert_eq!(cpi.result, 0);
+        assert_eq!(cpi.cus_consumed, None);
+    }
+
+    #[test]
+    fn test_instr_context_slot_context() {
+        let blockhash = Hash::new_unique();
+        let input = proto::InstrContext {
+            program_id: vec![0u8; 32],
+            accounts: vec![],
+            instr_accounts: vec![],
+            data: vec![],
+            cu_avail: 10000u64,
+            epoch_context: None,
+            slot_context: Some(proto::SlotContext {
+                slot: Some(432000),
+                blockhash: blockhash.to_bytes().to_vec(),
+                lamports_per_signature: 5000,
+                last_restart_slot: 400000,
+            }),
+            additional_instrs: vec![],
+            instr_trace_enabled: false,
+            logs_enabled: false,
+            added_builtins: vec![],
+            removed_builtins: vec![],
+        };
+        let instr_context = InstrContext::try_from(input).unwrap();
+        assert_eq!(instr_context.last_blockhash, blockhash);
+        assert_eq!(instr_context.lamports_per_signature, 5000);
+
+        assert_eq!(
+            instr_context.rent_collector.epoch,
+            EpochSchedule::default().get_epoch(432000)
+        );
+
+        let mut sysvar_cache = SysvarCache::default();
+        fill_sysvar_cache(&mut sysvar_cache, &instr_context);
+        let clock = sysvar_cache.get_clock().unwrap();
+        assert_eq!(clock.slot, 432000);
+        assert_eq!(clock.epoch, EpochSchedule::default().get_epoch(432000));
+        assert_eq!(
+            sysvar_cache
+                .get_last_restart_slot()
+                .unwrap()
+                .last_restart_slot,
+            400000
+        );
+    }
+
+    #[test]
+    fn test_instr_context_slot_context_fallback() {
+        let sysvar_account = |address: Pubkey, data: Vec<u8>| proto::AcctState {
+            address: address.to_bytes().to_vec(),
+            owner: sysvar::id().to_bytes().to_vec(),
+            lamports: 1,
+            data,
+            executable: false,
+            rent_epoch: 0,
+            seed_addr: None,
+        };
+        let clock = Clock {
+            slot: 1000,
+            epoch: 7,
+            ..Clock::default()
+        };
+        let rent = Rent {
+            lamports_per_byte_year: 1,
+            ..Rent::default()
+        };
+        let input = proto::InstrContext {
+            program_id: vec![0u8; 32],
+            accounts: vec![
+                sysvar_account(Clock::id(), bincode::serialize(&clock).unwrap()),
+                sysvar_account(Rent::id(), bincode::serialize(&rent).unwrap()),
+            ],
+            instr_accounts: vec![],
+            data: vec![],
+            cu_avail: 10000u64,
+            epoch_context: None,
+            slot_context: Some(proto::SlotContext {
+                slot: Some(2000),
+                blockhash: vec![],
+                lamports_per_signature: 0,
+                last_restart_slot: 0,
+            }),
+            additional_instrs: vec![],
+            instr_trace_enabled: false,
+            logs_enabled: false,
+            added_builtins: vec![],
+            removed_builtins: vec![],
+        };
+
+        // The slot context slot takes precedence over the Clock account, the
+        // Clock account over the default. The epoch follows the slot.
+        let instr_context = InstrContext::try_from(input.clone()).unwrap();
+        assert_eq!(instr_context.clock.slot, 2000);
+        assert_eq!(
+            instr_context.clock.epoch,
+            EpochSchedule::default().get_epoch(2000)
+        );
+        assert_eq!(instr_context.last_restart_slot, 5000);
+        assert_eq!(instr_context.last_blockhash, Hash::default());
+        assert_eq!(instr_context.lamports_per_signature, 0);
+        assert_eq!(
+            instr_context.rent_collector.epoch,
+            EpochSchedule::default().get_epoch(2000)
+        );
+        assert_eq!(instr_context.rent_collector.rent, rent);
+
+        let instr_context = InstrContext::try_from(proto::InstrContext {
+            slot_context: Some(proto::SlotContext::default()),
+            ..input.clone()
+        })
+        .unwrap();
+        assert_eq!(instr_context.clock, clock);
+        assert_eq!(instr_context.rent_collector.epoch, 7);
+
+        // Slot 0 can be set explicitly
+        let instr_context = InstrContext::try_from(proto::InstrContext {
+            slot_context: Some(proto::SlotContext {
+                slot: Some(0),
+                ..proto::SlotContext::default()
+            }),
+            ..input.clone()
+        })
+        .unwrap();
+        assert_eq!(instr_context.clock.slot, 0);
+        assert_eq!(instr_context.clock.epoch, 0);
+
+        let instr_context = InstrContext::try_from(proto::InstrContext {
+            accounts: vec![],
+            slot_context: Some(proto::SlotContext::default()),
+            ..input
+        })
+        .unwrap();
+        assert_eq!(instr_context.clock.slot, 10);
+        assert_eq!(instr_context.last_restart_slot, 5000);
+        assert_eq!(instr_context.rent_collector.rent, Rent::default());
+    }
+
+    #[test]
+    fn test_removed_builtin() {
+        let input = proto::InstrContext {
+            removed_builtins: vec![solana_system_program::id().to_bytes().to_vec()],
+            ..system_transfer_context(1)
+        };
+        let output = execute_instr_proto(input).unwrap();
+        assert_eq!(
+            output.result,
+            instr_err_to_num(&InstructionError::UnsupportedProgramId)
+        );
+        assert_eq!(output.modified_accounts[1].lamports, 0);
+    }
+
+    #[test]
+    fn test_batch_round_trip() {
+        // The malformed program ID is rejected by the harness
+        let contexts = vec![
+            system_transfer_context(1),
+            proto::InstrContext {
+                program_id: vec![0u8; 31],
+                ..system_transfer_context(1)
+            },
+            system_transfer_context(2000),
+            system_transfer_context(3),
+        ];
+
+        let mut input = proto::BatchContext {
+            contexts: contexts
+                .iter()
+                .map(|context| context.encode_to_vec())
+                .collect(),
+        }
+        .encode_to_vec();
+        let mut output = vec![0u8; 1 << 20];
+        let mut output_size = output.len() as u64;
+        let ok = unsafe {
+            sol_compat_instr_execute_batch_v1(
+                output.as_mut_ptr(),
+                &mut output_size,
+                input.as_mut_ptr(),
+                input.len() as u64,
+            )
+        };
+        assert_eq!(ok, 1);
+        let batch_effects = proto::BatchEffects::decode(&output[..output_size as usize]).unwrap();
+
+        assert_eq!(batch_effects.failed, vec![1]);
+        assert_eq!(batch_effects.effects.len(), contexts.len());
+        for (context, effects) in contexts.into_iter().zip(batch_effects.effects) {
+            let expected = execute_instr_proto(context)
+                .map(|effects| effects.encode_to_vec())
+                .unwrap_or_default();
+            assert_eq!(effects, expected);
+        }
+    }
+
+    #[test]
+    fn test_batch_cache_feature_sets() {
+        let direct_mapping = bpf_account_data_direct_mapping::id();
+        let features = [
+            proto::FeatureSet { features: vec![] },
+            proto::FeatureSet {
+                features: vec![utils::feature_u64(&direct_mapping)],
+            },
+            proto::FeatureSet { features: vec![] },
+        ];
+
+        let mut input = proto::BatchContext {
+            contexts: features
+                .iter()
+                .map(|features| features.encode_to_vec())
+                .collect(),
+        }
+        .encode_to_vec();
+        let mut output = vec![0u8; 1 << 16];
+        let mut output_size = output.len() as u64;
+        let environments = std::cell::RefCell::new(vec![]);
+        let ok = unsafe {
+            batch::execute_batch(
+                output.as_mut_ptr(),
+                &mut output_size,
+                input.as_mut_ptr(),
+                input.len() as u64,
+                |features: proto::FeatureSet| {
+                    let feature_set = FeatureSet::from(&features);
+                    let environment = batch::program_runtime_environment_v1(
+                        &feature_set,
+                        &ComputeBudget::default(),
+                        false,
+                        false,
+                    );
+                    environments.borrow_mut().push((feature_set, environment));
+                    Some(features)
+                },
+            )
+        };
+        assert_eq!(ok, 1);
+
+        // Contexts with the same features share the cached entries, but
+        // different feature sets don't
+        let environments = environments.into_inner();
+        assert!(!environments[0].0.is_active(&direct_mapping));
+        assert!(environments[1].0.is_active(&direct_mapping));
+        assert!(!Arc::ptr_eq(&environments[0].1, &environments[1].1));
+        assert_eq!(environments[0].0, environments[2].0);
+        assert!(Arc::ptr_eq(&environments[0].1, &environments[2].1));
+    }
 }
@@ -1,6 +1,16 @@
-use crate::proto::{PackComputeBudgetContext, PackComputeBudgetEffects};
+use crate::batch;
+use crate::proto::{
+    AcctState, PackComputeBudgetContext, PackComputeBudgetEffects, PackCostContext, PackCostEffects,
+};
+use crate::txn_fuzzer::build_versioned_transaction;
+use solana_cost_model::cost_model::CostModel;
 use solana_runtime_transaction::instructions_processor::process_compute_budget_instructions;
+use solana_sdk::address_lookup_table::state::AddressLookupTable;
 use solana_sdk::compute_budget;
+use solana_sdk::feature_set::FeatureSet;
+use solana_sdk::message::v0::{LoadedAddresses, MessageAddressTableLookup};
+use solana_sdk::reserved_account_keys::ReservedAccountKeys;
+use solana_sdk::transaction::{MessageHash, SanitizedTransaction, SimpleAddressLoader};
 use solana_sdk::{fee::FeeBudgetLimits, pubkey::Pubkey};
 use solana_svm_transaction::instruction::SVMInstruction;
 use {prost::Message, std::ffi::c_int};
@@ -35,6 +45,16 @@ pub unsafe extern "C" fn sol_compat_pack_compute_budget_v1(
     1
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_pack_compute_budget_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_pack_cbp)
+}
+
 fn execute_pack_cbp(input: PackComputeBudgetContext) -> Option<PackComputeBudgetEffects> {
     let mut svm_instrs: Vec<(&Pubkey, SVMInstruction)> = Vec::new();
     let program_id = compute_budget::id();
@@ -68,3 +88,135 @@ fn execute_pack_cbp(input: PackComputeBudgetContext) -> Option<PackComputeBudget
         Err(_) => Some(PackComputeBudgetEffects::default()),
     }
 }
+
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_pack_cost_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    let in_slice = std::slice::from_raw_parts(in_ptr, in_sz as usize);
+    let input = match PackCostContext::decode(in_slice) {
+        Ok(input) => input,
+        Err(_) => return 0,
+    };
+
+    let effects = match execute_pack_cost(input) {
+        Some(effects) => effects,
+        None => return 0,
+    };
+
+    let out_slice = std::slice::from_raw_parts_mut(out_ptr, *out_psz as usize);
+    let effects_vec = effects.encode_to_vec();
+    if out_slice.len() < effects_vec.len() {
+        return 0;
+    }
+
+    out_slice[..effects_vec.len()].copy_from_slice(&effects_vec);
+    *out_psz = effects_vec.len() as u64;
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_pack_cost_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_pack_cost)
+}
+
+/* Resolves address table lookups against the lookup table accounts in
+`account_shared_data`. Like in pack, the table's activation status isn't
+checked. */
+fn load_addresses(
+    lookups: &[MessageAddressTableLookup],
+    account_shared_data: &[AcctState],
+) -> Option<LoadedAddresses> {
+    let mut loaded_addresses = LoadedAddresses::default();
+    for lookup in lookups {
+        let table_account = account_shared_data
+            .iter()
+            .find(|account| account.address.as_slice() == lookup.account_key.as_ref())?;
+        let table = AddressLookupTable::deserialize(&table_account.data).ok()?;
+        for index in &lookup.writable_indexes {
+            loaded_addresses
+                .writable
+                .push(*table.addresses.get(*index as usize)?);
+        }
+        for index in &lookup.readonly_indexes {
+            loaded_addresses
+                .readonly
+                .push(*table.addresses.get(*index as usize)?);
+        }
+    }
+    Some(loaded_addresses)
+}
+
+fn sanitization_error_effects() -> PackCostEffects {
+    PackCostEffects {
+        sanitization_error: true,
+        ..PackCostEffects::default()
+    }
+}
+
+/* Runs the cost model on the transaction. Transactions that can't be
+sanitized, including ones whose lookup tables can't be resolved, have
+`sanitization_error` set and no costs.
+
+In this version of the cost model, builtin and BPF instructions share a single
+execution cost (`programs_execution_cost`): the sum of the builtins' default
+costs and of the default compute unit limit of the other instructions, or the
+requested compute unit limit if set. */
+pub fn execute_pack_cost(input: PackCostContext) -> Option<PackCostEffects> {
+    let feature_set = input
+        .features
+        .as_ref()
+        .map(FeatureSet::from)
+        .unwrap_or_default();
+    let tx = input.tx?;
+
+    let Some(versioned_transaction) = build_versioned_transaction(&tx, false) else {
+        return Some(sanitization_error_effects());
+    };
+    let account_shared_data = tx
+        .message
+        .as_ref()
+        .map(|message| message.account_shared_data.as_slice())
+        .unwrap_or_default();
+    let loaded_addresses = match versioned_transaction.message.address_table_lookups() {
+        Some(lookups) => match load_addresses(lookups, account_shared_data) {
+            Some(loaded_addresses) => loaded_addresses,
+            None => return Some(sanitization_error_effects()),
+        },
+        None => LoadedAddresses::default(),
+    };
+
+    let mut reserved_account_keys = ReservedAccountKeys::default();
+    reserved_account_keys.update_active_set(&feature_set);
+    let Ok(sanitized_transaction) = SanitizedTransaction::try_create(
+        versioned_transaction,
+        MessageHash::Compute,
+        Some(tx.is_simple_vote_tx),
+        SimpleAddressLoader::Enabled(loaded_addresses),
+        &reserved_account_keys.active,
+    ) else {
+        return Some(sanitization_error_effects());
+    };
+
+    let cost = CostModel::calculate_cost(&sanitized_transaction, &feature_set);
+    Some(PackCostEffects {
+        is_simple_vote: cost.is_simple_vote(),
+        signature_cost: cost.signature_cost(),
+        write_lock_cost: cost.write_lock_cost(),
+        data_bytes_cost: u64::from(cost.data_bytes_cost()),
+        programs_execution_cost: cost.programs_execution_cost(),
+        loaded_accounts_data_size_cost: cost.loaded_accounts_data_size_cost(),
+        allocated_accounts_data_size: cost.allocated_accounts_data_size(),
+        total_cost: cost.sum(),
+        sanitization_error: false,
+    })
+}
@@ -1,3 +1,4 @@
+use crate::batch;
 use crate::proto::{AcceptsShred, ShredBinary};
 use prost::Message;
 use solana_ledger::shred::Shred;
@@ -17,13 +18,7 @@ pub unsafe extern "C" fn sol_compat_shred_parse_v1(
         Err(_) => return 0,
     };
 
-    let shred_bytes = binary_shred.data;
-
-    let accepts_shred = match Shred::new_from_serialized_shred(shred_bytes) {
-        // Not sure why this memory leaks
-        Ok(_) => AcceptsShred { valid: true },
-        Err(_) => AcceptsShred { valid: false },
-    };
+    let accepts_shred = execute_shred_parse(binary_shred);
 
     let out_slice = std::slice::from_raw_parts_mut(out_ptr, (*out_psz) as usize);
     let out_bytes = accepts_shred.encode_to_vec();
@@ -35,3 +30,23 @@ pub unsafe extern "C" fn sol_compat_shred_parse_v1(
 
     1
 }
+
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_shred_parse_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, |binary_shred| {
+        Some(execute_shred_parse(binary_shred))
+    })
+}
+
+fn execute_shred_parse(binary_shred: ShredBinary) -> AcceptsShred {
+    match Shred::new_from_serialized_shred(binary_shred.data) {
+        // Not sure why this memory leaks
+        Ok(_) => AcceptsShred { valid: true },
+        Err(_) => AcceptsShred { valid: false },
+    }
+}
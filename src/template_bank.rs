@@ -0,0 +1,140 @@
+use crate::txn_fuzzer::{new_genesis_bank_forks, GenesisParams};
+use solana_program_runtime::loaded_programs::{ProgramCacheEntry, ProgramRuntimeEnvironments};
+use solana_runtime::bank_forks::BankForks;
+use solana_sdk::clock::{Epoch, Slot};
+use solana_sdk::pubkey::Pubkey;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+
+/* Genesis banks shared by transaction harness invocations.
+
+Creating a genesis bank (accounts DB, builtins, rehash) costs far more than
+executing a transaction, so genesis banks are kept per thread and keyed by
+their `GenesisParams`. Fixtures execute on child banks of the template. When
+the fork is dropped, the child banks are removed and their slots purged from
+the accounts DB, and the program cache is reset to its genesis state. */
+
+const MAX_TEMPLATES: usize = 4;
+
+struct Template {
+    params: GenesisParams,
+    bank_forks: Arc<RwLock<BankForks>>,
+    // Program cache state of the genesis bank
+    latest_root_slot: Slot,
+    latest_root_epoch: Epoch,
+    environments: ProgramRuntimeEnvironments,
+    programs: Vec<(Pubkey, Arc<ProgramCacheEntry>)>,
+}
+
+thread_local! {
+    static TEMPLATES: RefCell<VecDeque<Template>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/* Bank forks of a template, checked out for the duration of one fixture */
+pub(crate) struct TemplateFork {
+    template: Option<Template>,
+    program_ids: Vec<Pubkey>,
+}
+
+/* Checks out the template for `params`, creating it if needed. `program_ids`
+are the accounts of the fixture that may end up in the program cache. */
+pub(crate) fn fork(params: GenesisParams, program_ids: Vec<Pubkey>) -> TemplateFork {
+    let cached = TEMPLATES.with(|templates| {
+        let mut templates = templates.borrow_mut();
+        let position = templates
+            .iter()
+            .position(|template| template.params == params)?;
+        templates.remove(position)
+    });
+    let template = cached.unwrap_or_else(|| {
+        let bank_forks = new_genesis_bank_forks(&params);
+        let root_bank = bank_forks.read().unwrap().root_bank();
+        let (latest_root_slot, latest_root_epoch, environments, programs) = {
+            let program_cache = root_bank
+                .get_transaction_processor()
+                .program_cache
+                .read()
+                .unwrap();
+            (
+                program_cache.latest_root_slot,
+                program_cache.latest_root_epoch,
+                program_cache.environments.clone(),
+                program_cache.get_flattened_entries_for_tests(),
+            )
+        };
+        Template {
+            params,
+            bank_forks,
+            latest_root_slot,
+            latest_root_epoch,
+            environments,
+            programs,
+        }
+    });
+
+    TemplateFork {
+        template: Some(template),
+        program_ids,
+    }
+}
+
+impl TemplateFork {
+    pub(crate) fn bank_forks(&self) -> &Arc<RwLock<BankForks>> {
+        &self.template.as_ref().unwrap().bank_forks
+    }
+
+    fn reset(&mut self, template: &Template) {
+        let mut bank_forks = template.bank_forks.write().unwrap();
+        let root_bank = bank_forks.root_bank();
+        let forks = bank_forks
+            .banks()
+            .iter()
+            .filter(|(slot, _)| **slot != root_bank.slot())
+            .map(|(slot, bank)| (*slot, bank.bank_id()))
+            .collect::<Vec<_>>();
+        for (slot, _) in &forks {
+            bank_forks.remove(*slot);
+        }
+        drop(bank_forks);
+        // Dropping the banks purges their slots, unless a bank outlives the
+        // fixture
+        root_bank.remove_unrooted_slots(&forks);
+
+        let mut program_cache = root_bank
+            .get_transaction_processor()
+            .program_cache
+            .write()
+            .unwrap();
+        program_cache.remove_programs(self.program_ids.iter().copied());
+        // Genesis entries (e.g. builtins the fixture has an account for) are
+        // restored, so later fixtures don't load them from their accounts
+        for (program_id, entry) in &template.programs {
+            if self.program_ids.contains(program_id) {
+                program_cache.assign_program(*program_id, entry.clone());
+            }
+        }
+        self.program_ids.clear();
+        program_cache.latest_root_slot = template.latest_root_slot;
+        program_cache.latest_root_epoch = template.latest_root_epoch;
+        program_cache.environments = template.environments.clone();
+        program_cache.upcoming_environments = None;
+        program_cache.programs_to_recompile.clear();
+    }
+}
+
+impl Drop for TemplateFork {
+    fn drop(&mut self) {
+        let Some(template) = self.template.take() else {
+            return;
+        };
+        self.reset(&template);
+        TEMPLATES.with(|templates| {
+            let mut templates = templates.borrow_mut();
+            if templates.len() == MAX_TEMPLATES {
+                templates.pop_front();
+            }
+            templates.push_back(template);
+        });
+    }
+}
@@ -1,17 +1,27 @@
+use crate::batch;
 use crate::proto::{self, ResultingState};
 use crate::proto::{AcctState, TransactionMessage, TxnContext, TxnResult};
+use crate::template_bank;
+use crate::Error;
 use prost::Message;
 use solana_accounts_db::accounts_db::AccountsDbConfig;
 use solana_accounts_db::accounts_file::StorageAccess;
 use solana_accounts_db::accounts_index::{AccountsIndexConfig, IndexLimitMb};
+use solana_program::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
 use solana_program::hash::Hash;
 use solana_program::instruction::CompiledInstruction;
 use solana_program::message::v0::MessageAddressTableLookup;
 use solana_program::message::{legacy, v0, MessageHeader, VersionedMessage};
 use solana_program::pubkey::Pubkey;
+use solana_program::{bpf_loader, bpf_loader_deprecated};
+use solana_program_runtime::loaded_programs::{
+    LoadProgramMetrics, ProgramCacheEntry, ProgramCacheEntryOwner, ProgramCacheEntryType,
+    DELAY_VISIBILITY_SLOT_OFFSET,
+};
 use solana_runtime::bank::builtins::BUILTINS;
 use solana_runtime::bank::{Bank, LoadAndExecuteTransactionsOutput};
 use solana_runtime::bank_forks::BankForks;
+use solana_runtime_transaction::instructions_processor::process_compute_budget_instructions;
 use solana_sdk::account::{AccountSharedData, ReadableAccount};
 use solana_sdk::clock::MAX_PROCESSING_AGE;
 use solana_sdk::epoch_schedule::EpochSchedule;
@@ -19,27 +29,32 @@ use solana_sdk::feature_set::FeatureSet;
 use solana_sdk::genesis_config::GenesisConfig;
 use solana_sdk::instruction::InstructionError;
 use solana_sdk::message::SanitizedMessage;
+use solana_sdk::native_loader;
 use solana_sdk::rent::Rent;
-use solana_sdk::signature::Signature;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::signer::keypair::keypair_from_seed;
 use solana_sdk::sysvar;
 use solana_sdk::transaction::{
     TransactionError, TransactionVerificationMode, VersionedTransaction,
 };
 use solana_sdk::transaction_context::TransactionAccount;
 use solana_svm::account_loader::LoadedTransaction;
+use solana_svm::rollback_accounts::RollbackAccounts;
 use solana_svm::runtime_config::RuntimeConfig;
 use solana_svm::transaction_error_metrics::TransactionErrorMetrics;
+use solana_svm::transaction_execution_result::InnerInstructionsList;
 use solana_svm::transaction_processing_result::{
     ProcessedTransaction, TransactionProcessingResultExtensions,
 };
 use solana_svm::transaction_processor::{ExecutionRecordingConfig, TransactionProcessingConfig};
+use solana_svm_transaction::instruction::SVMInstruction;
 use solana_timings::ExecuteTimings;
 use std::cmp::max;
 use std::collections::HashSet;
 use std::ffi::c_int;
 use std::num::NonZeroUsize;
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 #[no_mangle]
 pub unsafe extern "C" fn sol_compat_txn_execute_v1(
@@ -74,6 +89,16 @@ pub unsafe extern "C" fn sol_compat_txn_execute_v1(
     1
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_txn_execute_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_transaction)
+}
+
 impl From<&proto::MessageHeader> for MessageHeader {
     fn from(value: &proto::MessageHeader) -> Self {
         MessageHeader {
@@ -94,10 +119,13 @@ impl From<&proto::CompiledInstruction> for CompiledInstruction {
     }
 }
 
-impl From<&proto::MessageAddressTableLookup> for MessageAddressTableLookup {
-    fn from(value: &proto::MessageAddressTableLookup) -> Self {
-        MessageAddressTableLookup {
-            account_key: Pubkey::new_from_array(value.account_key.clone().try_into().unwrap()),
+impl TryFrom<&proto::MessageAddressTableLookup> for MessageAddressTableLookup {
+    type Error = Error;
+
+    fn try_from(value: &proto::MessageAddressTableLookup) -> Result<Self, Self::Error> {
+        Ok(MessageAddressTableLookup {
+            account_key: Pubkey::try_from(value.account_key.as_slice())
+                .map_err(|_| Error::InvalidPubkeyBytes)?,
             writable_indexes: value
                 .writable_indexes
                 .iter()
@@ -108,10 +136,11 @@ impl From<&proto::MessageAddressTableLookup> for MessageAddressTableLookup {
                 .iter()
                 .map(|idx| *idx as u8)
                 .collect(),
-        }
+        })
     }
 }
 
+/* Returns None if the message contains malformed keys or hashes */
 fn build_versioned_message(value: &TransactionMessage) -> Option<VersionedMessage> {
     let header = if let Some(value_header) = value.header {
         MessageHeader::from(&value_header)
@@ -126,13 +155,13 @@ fn build_versioned_message(value: &TransactionMessage) -> Option<VersionedMessag
     let account_keys = value
         .account_keys
         .iter()
-        .map(|key| Pubkey::new_from_array(key.clone().try_into().unwrap()))
-        .collect::<Vec<Pubkey>>();
+        .map(|key| Pubkey::try_from(key.as_slice()).ok())
+        .collect::<Option<Vec<Pubkey>>>()?;
     let recent_blockhash = if value.recent_blockhash.is_empty() {
         // Default: empty blockchash (this keeps tests simpler)
         Hash::new_from_array([0u8; 32])
     } else {
-        Hash::new(&value.recent_blockhash)
+        Hash::new_from_array(value.recent_blockhash.clone().try_into().ok()?)
     };
     let instructions = value
         .instructions
@@ -152,8 +181,8 @@ fn build_versioned_message(value: &TransactionMessage) -> Option<VersionedMessag
         let address_table_lookups = value
             .address_table_lookups
             .iter()
-            .map(MessageAddressTableLookup::from)
-            .collect::<Vec<MessageAddressTableLookup>>();
+            .map(|lookup| MessageAddressTableLookup::try_from(lookup).ok())
+            .collect::<Option<Vec<MessageAddressTableLookup>>>()?;
 
         let message = v0::Message {
             header,
@@ -225,12 +254,43 @@ impl From<LoadedTransaction> for proto::ResultingState {
             acct_states,
             rent_debits,
             transaction_rent: value.rent,
+            loaded_accounts: vec![],
         }
     }
 }
 
-impl From<LoadAndExecuteTransactionsOutput> for TxnResult {
-    fn from(value: LoadAndExecuteTransactionsOutput) -> TxnResult {
+/* Post-states of the accounts committed for a fee-only transaction. The fee
+payer comes first, followed by the nonce account if it's a separate account. */
+fn rollback_accounts_to_proto(
+    rollback_accounts: &RollbackAccounts,
+    fee_payer: &Pubkey,
+) -> ResultingState {
+    let acct_states = match rollback_accounts {
+        RollbackAccounts::FeePayerOnly { fee_payer_account } => {
+            vec![(*fee_payer, fee_payer_account.clone()).into()]
+        }
+        RollbackAccounts::SameNonceAndFeePayer { nonce } => {
+            vec![(*nonce.address(), nonce.account().clone()).into()]
+        }
+        RollbackAccounts::SeparateNonceAndFeePayer {
+            nonce,
+            fee_payer_account,
+        } => vec![
+            (*fee_payer, fee_payer_account.clone()).into(),
+            (*nonce.address(), nonce.account().clone()).into(),
+        ],
+    };
+
+    ResultingState {
+        acct_states,
+        rent_debits: vec![],
+        transaction_rent: 0,
+        loaded_accounts: vec![],
+    }
+}
+
+impl From<&LoadAndExecuteTransactionsOutput> for TxnResult {
+    fn from(value: &LoadAndExecuteTransactionsOutput) -> TxnResult {
         let execution_results = &value.processing_results[0];
         let (
             is_ok,
@@ -335,62 +395,292 @@ impl From<LoadAndExecuteTransactionsOutput> for TxnResult {
                 transaction_fee: fees.transaction_fee(),
                 prioritization_fee: fees.prioritization_fee(),
             }),
+            inner_instructions: vec![],
+            logs: vec![],
+            return_data_program_id: vec![],
+            epoch_boundary_accounts: vec![],
+            loaded_accounts_data_size: 0,
+            loaded_accounts_data_size_estimated: false,
+            loaded_accounts_data_size_limit: 0,
+            loaded_account_data_sizes: vec![],
         }
     }
 }
 
-#[allow(deprecated)]
+/* Like RPC's `innerInstructions`, only top-level instructions that invoked
+other instructions are listed */
+fn inner_instructions_to_proto(
+    inner_instructions: &InnerInstructionsList,
+) -> Vec<proto::InnerInstructions> {
+    inner_instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instructions)| !instructions.is_empty())
+        .map(|(index, instructions)| proto::InnerInstructions {
+            index: index as u32,
+            instructions: instructions
+                .iter()
+                .map(|inner| proto::InnerInstruction {
+                    program_id_index: inner.instruction.program_id_index as u32,
+                    accounts: inner
+                        .instruction
+                        .accounts
+                        .iter()
+                        .map(|idx| *idx as u32)
+                        .collect(),
+                    data: inner.instruction.data.clone(),
+                    stack_height: inner.stack_height as u32,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
 pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
     let fd_features = context
         .epoch_ctx
         .as_ref()
         .map(|ctx| ctx.features.clone().unwrap_or_default())
         .unwrap_or_default();
+    let slot = context
+        .slot_ctx
+        .as_ref()
+        .and_then(|ctx| ctx.slot)
+        .unwrap_or(10); // Arbitrary default > 0
 
-    let feature_set = FeatureSet::from(&fd_features);
-    let fee_collector = Pubkey::new_unique();
-    let slot = context.slot_ctx.as_ref().map(|ctx| ctx.slot).unwrap_or(10); // Arbitrary default > 0
+    let account_shared_data = &context.tx.as_ref()?.message.as_ref()?.account_shared_data;
 
-    /* HACK: Set the genesis config rent and epoch schedule from the "to-be" sysvars, if present */
-    let rent: Rent = context
-        .tx
-        .as_ref()?
-        .message
-        .as_ref()?
-        .account_shared_data
-        .iter()
-        .find(|item| item.address.as_slice() == sysvar::rent::id().as_ref() && item.lamports > 0)
-        .map(|account| bincode::deserialize(&account.data).ok())
-        .unwrap_or_default()
-        .unwrap_or_default();
-    let epoch_schedule: EpochSchedule = context
-        .tx
-        .as_ref()?
-        .message
-        .as_ref()?
-        .account_shared_data
+    let blockhash_queue = parse_blockhash_queue(context.blockhash_queue)?;
+    let params = GenesisParams::new(&fd_features, account_shared_data, &blockhash_queue);
+
+    // At slot 0 the transaction executes on the genesis bank itself, which
+    // can't be shared
+    let template_fork;
+    let bank_forks = if slot > 0 {
+        let program_ids = account_shared_data
+            .iter()
+            .map(|account| &account.address)
+            .chain(
+                context
+                    .program_deployments
+                    .iter()
+                    .map(|deployment| &deployment.program_id),
+            )
+            .filter_map(|address| Pubkey::try_from(address.as_slice()).ok())
+            .collect();
+        template_fork = template_bank::fork(params, program_ids);
+        template_fork.bank_forks().clone()
+    } else {
+        new_genesis_bank_forks(&params)
+    };
+    let mut bank = load_bank(&bank_forks, slot, account_shared_data, &blockhash_queue)?;
+
+    let mut epoch_boundary_accounts = vec![];
+    if context.epoch_boundary {
+        let parent = bank.clone();
+        let first_slot = parent
+            .epoch_schedule()
+            .get_first_slot_in_epoch(parent.epoch() + 1);
+        bank = new_child_bank(&bank_forks, parent.clone(), first_slot);
+        epoch_boundary_accounts = epoch_boundary_changes(&parent, &bank, account_shared_data);
+    }
+
+    deploy_programs(&bank, &context.program_deployments)?;
+
+    let mut txn_result = process_transaction(
+        &bank,
+        context.tx.as_ref()?,
+        false,
+        &TxnOptions::from(&context),
+    )?;
+    txn_result.epoch_boundary_accounts = epoch_boundary_accounts;
+    Some(txn_result)
+}
+
+/* Returns the ELF of a program deployed by one of the BPF loaders */
+fn program_elf(bank: &Bank, program_account: &AccountSharedData) -> Option<Vec<u8>> {
+    if bpf_loader_upgradeable::check_id(program_account.owner()) {
+        let UpgradeableLoaderState::Program {
+            programdata_address,
+        } = bincode::deserialize(program_account.data()).ok()?
+        else {
+            return None;
+        };
+        let programdata_account = bank.get_account(&programdata_address)?;
+        programdata_account
+            .data()
+            .get(UpgradeableLoaderState::size_of_programdata_metadata()..)
+            .map(<[u8]>::to_vec)
+    } else if bpf_loader::check_id(program_account.owner())
+        || bpf_loader_deprecated::check_id(program_account.owner())
+    {
+        Some(program_account.data().to_vec())
+    } else {
+        None
+    }
+}
+
+/* Adds program cache entries for programs deployed at earlier slots (or at
+the current slot), like those a live cluster would have cached. Entries are
+built from the deployment's ELF if set (e.g. a version of the program before
+it was upgraded or closed), and from the fixture's program accounts otherwise.
+Returns None if a deployment is in the future, isn't owned by a BPF loader, or
+has no ELF. */
+fn deploy_programs(bank: &Bank, deployments: &[proto::ProgramDeployment]) -> Option<()> {
+    let environment = bank
+        .get_transaction_processor()
+        .program_cache
+        .read()
+        .unwrap()
+        .environments
+        .program_runtime_v1
+        .clone();
+
+    for deployment in deployments {
+        if deployment.slot > bank.slot() {
+            return None;
+        }
+        let program_id = Pubkey::try_from(deployment.program_id.as_slice()).ok()?;
+        let program_account = bank.get_account(&program_id);
+        let loader_key = program_account
+            .as_ref()
+            .map(|account| *account.owner())
+            .unwrap_or(bpf_loader_upgradeable::id());
+        let owner = ProgramCacheEntryOwner::try_from(&loader_key).ok()?;
+        if !matches!(
+            owner,
+            ProgramCacheEntryOwner::LoaderV1
+                | ProgramCacheEntryOwner::LoaderV2
+                | ProgramCacheEntryOwner::LoaderV3
+        ) {
+            return None;
+        }
+        let elf = if deployment.elf.is_empty() {
+            program_elf(bank, program_account.as_ref()?)?
+        } else {
+            deployment.elf.clone()
+        };
+
+        let effective_slot = deployment.slot.saturating_add(DELAY_VISIBILITY_SLOT_OFFSET);
+        let entry = ProgramCacheEntry::new(
+            &loader_key,
+            environment.clone(),
+            deployment.slot,
+            effective_slot,
+            &elf,
+            program_account.map_or(elf.len(), |account| account.data().len()),
+            &mut LoadProgramMetrics::default(),
+        )
+        .unwrap_or_else(|_| {
+            ProgramCacheEntry::new_tombstone(
+                deployment.slot,
+                owner,
+                ProgramCacheEntryType::FailedVerification(environment.clone()),
+            )
+        });
+        bank.get_transaction_processor()
+            .program_cache
+            .write()
+            .unwrap()
+            .assign_program(program_id, Arc::new(entry));
+    }
+    Some(())
+}
+
+/* Post-states of the sysvars and fixture accounts (e.g. reward accounts)
+modified when crossing from `parent` into `bank`, before the transaction is
+executed */
+fn epoch_boundary_changes(
+    parent: &Bank,
+    bank: &Bank,
+    account_shared_data: &[AcctState],
+) -> Vec<AcctState> {
+    let mut seen = HashSet::<Pubkey>::new();
+    sysvar::ALL_IDS
         .iter()
-        .find(|item| {
-            item.address.as_slice() == sysvar::epoch_schedule::id().as_ref() && item.lamports > 0
+        .copied()
+        .chain(
+            account_shared_data
+                .iter()
+                .filter_map(|account| Pubkey::try_from(account.address.as_slice()).ok()),
+        )
+        .filter(|pubkey| seen.insert(*pubkey))
+        .filter_map(|pubkey| {
+            let account = bank.get_account(&pubkey)?;
+            if parent.get_account(&pubkey).as_ref() == Some(&account) {
+                return None;
+            }
+            Some((pubkey, account).into())
         })
-        .map(|account| bincode::deserialize(&account.data).ok())
-        .unwrap_or_default()
-        .unwrap_or_default();
+        .collect()
+}
+
+/* Parameters of the genesis bank a fixture executes on */
+#[derive(PartialEq)]
+pub(crate) struct GenesisParams {
+    pub(crate) feature_set: FeatureSet,
+    pub(crate) rent: Rent,
+    pub(crate) epoch_schedule: EpochSchedule,
+    pub(crate) genesis_hash: Hash,
+}
+
+impl GenesisParams {
+    /* The genesis hash is the first hash of `blockhash_queue` */
+    fn new(
+        fd_features: &proto::FeatureSet,
+        account_shared_data: &[AcctState],
+        blockhash_queue: &[Hash],
+    ) -> Self {
+        /* HACK: Set the genesis config rent and epoch schedule from the "to-be" sysvars, if present */
+        let rent: Rent = account_shared_data
+            .iter()
+            .find(|item| {
+                item.address.as_slice() == sysvar::rent::id().as_ref() && item.lamports > 0
+            })
+            .map(|account| bincode::deserialize(&account.data).ok())
+            .unwrap_or_default()
+            .unwrap_or_default();
+        let epoch_schedule: EpochSchedule = account_shared_data
+            .iter()
+            .find(|item| {
+                item.address.as_slice() == sysvar::epoch_schedule::id().as_ref()
+                    && item.lamports > 0
+            })
+            .map(|account| bincode::deserialize(&account.data).ok())
+            .unwrap_or_default()
+            .unwrap_or_default();
+
+        GenesisParams {
+            feature_set: FeatureSet::from(fd_features),
+            rent,
+            epoch_schedule,
+            genesis_hash: blockhash_queue[0],
+        }
+    }
+}
+
+/* Returns None if a blockhash is malformed */
+fn parse_blockhash_queue(blockhash_queue: Vec<Vec<u8>>) -> Option<Vec<Hash>> {
+    if blockhash_queue.is_empty() {
+        return Some(vec![Hash::default()]);
+    }
+    blockhash_queue
+        .into_iter()
+        .map(|blockhash| Some(Hash::new_from_array(blockhash.try_into().ok()?)))
+        .collect()
+}
+
+/* Creates bank forks rooted at a genesis bank at slot 0 */
+pub(crate) fn new_genesis_bank_forks(params: &GenesisParams) -> Arc<RwLock<BankForks>> {
+    let fee_collector = Pubkey::new_unique();
 
     let genesis_config = GenesisConfig {
         creation_time: 0,
-        rent,
-        epoch_schedule,
+        rent: params.rent.clone(),
+        epoch_schedule: params.epoch_schedule.clone(),
         ..GenesisConfig::default()
     };
 
-    let mut blockhash_queue = if context.blockhash_queue.is_empty() {
-        vec![vec![0u8; 32]]
-    } else {
-        context.blockhash_queue
-    };
-    let genesis_hash = Some(Hash::new(blockhash_queue[0].as_slice()));
-
     // Bank on slot 0
     let index = Some(AccountsIndexConfig {
         bins: Some(2),
@@ -415,34 +705,44 @@ pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
         None,
         Some(fee_collector),
         Arc::new(AtomicBool::new(false)),
-        genesis_hash,
-        Some(feature_set.clone()),
+        Some(params.genesis_hash),
+        Some(params.feature_set.clone()),
     );
     let bank_forks = BankForks::new_rw_arc(bank);
-    let mut bank = bank_forks.read().unwrap().root_bank();
-    bank.rehash();
+    bank_forks.read().unwrap().root_bank().rehash();
+    bank_forks
+}
+
+/* Creates a bank at `slot` (via a single hop from a genesis bank at slot 0),
+then loads the given accounts, sysvars and blockhash queue into it. The bank
+forks are returned alongside the bank so that callers can create child banks. */
+pub(crate) fn setup_bank(
+    fd_features: &proto::FeatureSet,
+    slot: u64,
+    account_shared_data: &[AcctState],
+    blockhash_queue: Vec<Vec<u8>>,
+) -> Option<(Arc<RwLock<BankForks>>, Arc<Bank>)> {
+    let blockhash_queue = parse_blockhash_queue(blockhash_queue)?;
+    let params = GenesisParams::new(fd_features, account_shared_data, &blockhash_queue);
+    let bank_forks = new_genesis_bank_forks(&params);
+    let bank = load_bank(&bank_forks, slot, account_shared_data, &blockhash_queue)?;
+    Some((bank_forks, bank))
+}
 
+/* Creates a bank at `slot` from the genesis bank of `bank_forks` (the genesis
+bank itself if `slot` is 0), then loads the given accounts, sysvars and
+blockhash queue into it */
+fn load_bank(
+    bank_forks: &RwLock<BankForks>,
+    slot: u64,
+    account_shared_data: &[AcctState],
+    blockhash_queue: &[Hash],
+) -> Option<Arc<Bank>> {
+    let mut bank = bank_forks.read().unwrap().root_bank();
     if slot > 0 {
-        let new_bank = Bank::new_from_parent(bank.clone(), &fee_collector, slot);
-        bank = bank_forks
-            .write()
-            .unwrap()
-            .insert(new_bank)
-            .clone_without_scheduler();
-        bank.get_transaction_processor()
-            .program_cache
-            .write()
-            .unwrap()
-            .prune(slot, bank.epoch());
+        bank = new_child_bank(bank_forks, bank, slot);
     }
 
-    let account_keys = context
-        .tx
-        .as_ref()
-        .and_then(|tx| tx.message.as_ref())
-        .map(|message| message.account_keys.clone())
-        .unwrap_or_default();
-
     /* Save loaded builtins so we don't load them twice */
     let mut stored_accounts = HashSet::<Pubkey>::default();
     for builtin in BUILTINS.iter() {
@@ -459,12 +759,12 @@ pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
     NOTE: Like in FD, we store the first instance of an account's state for a given pubkey. Account states of already-seen
     pubkeys are ignored. */
     bank.get_transaction_processor().reset_sysvar_cache();
-    for account in &context.tx.as_ref()?.message.as_ref()?.account_shared_data {
+    for account in account_shared_data {
         let pubkey = Pubkey::new_from_array(account.address.clone().try_into().ok()?);
         if !stored_accounts.insert(pubkey) {
             continue;
         }
-        let account_data = AccountSharedData::from(account);
+        let account_data = AccountSharedData::try_from(account).ok()?;
         bank.store_account(&pubkey, &account_data);
     }
     bank.get_transaction_processor()
@@ -485,67 +785,347 @@ pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
     }
 
     // Register blockhashes in bank
-    for blockhash in blockhash_queue.iter_mut() {
-        let blockhash_hash = Hash::new_from_array(std::mem::take(blockhash).try_into().unwrap());
-        bank.register_recent_blockhash_for_test(&blockhash_hash, lamports_per_signature);
+    for blockhash in blockhash_queue.iter() {
+        bank.register_recent_blockhash_for_test(blockhash, lamports_per_signature);
     }
     bank.update_recent_blockhashes();
     bank.get_transaction_processor().reset_sysvar_cache();
     bank.get_transaction_processor()
         .fill_missing_sysvar_cache_entries(bank.as_ref());
 
-    let message = build_versioned_message(context.tx.as_ref()?.message.as_ref()?)?;
+    Some(bank)
+}
+
+/* Creates a child of `parent` at `slot`, registers it in the bank forks, and
+prunes the program cache to the new slot. The parent bank is frozen. */
+pub(crate) fn new_child_bank(
+    bank_forks: &RwLock<BankForks>,
+    parent: Arc<Bank>,
+    slot: u64,
+) -> Arc<Bank> {
+    let collector_id = *parent.collector_id();
+    let new_bank = Bank::new_from_parent(parent, &collector_id, slot);
+    let bank = bank_forks
+        .write()
+        .unwrap()
+        .insert(new_bank)
+        .clone_without_scheduler();
+    bank.get_transaction_processor()
+        .program_cache
+        .write()
+        .unwrap()
+        .prune(slot, bank.epoch());
+    bank
+}
+
+pub(crate) fn build_versioned_transaction(
+    tx: &proto::SanitizedTransaction,
+    verify_signatures: bool,
+) -> Option<VersionedTransaction> {
+    let message = build_versioned_message(tx.message.as_ref()?)?;
 
-    let mut signatures = context
-        .tx
-        .as_ref()?
+    let mut signatures = tx
         .signatures
         .iter()
-        .map(|item| {
-            Signature::from(<Vec<u8> as TryInto<[u8; 64]>>::try_into(item.clone()).unwrap())
-        })
-        .collect::<Vec<Signature>>();
-    if signatures.is_empty() {
+        .map(|item| Signature::try_from(item.as_slice()).ok())
+        .collect::<Option<Vec<Signature>>>()?;
+    if signatures.is_empty() && !verify_signatures {
         // Default: valid txn with 1 empty signature (this keeps tests simpler)
         signatures.push(Signature::default());
     }
 
-    let versioned_transaction = VersionedTransaction {
+    Some(VersionedTransaction {
         message,
         signatures,
+    })
+}
+
+fn sanitization_error_result(transaction_error: &TransactionError) -> TxnResult {
+    let (status, instruction_error, _custom_error, instruction_error_index) =
+        transaction_error_to_err_nums(transaction_error);
+    TxnResult {
+        executed: false,
+        sanitization_error: true,
+        resulting_state: None,
+        rent: 0,
+        is_ok: false,
+        status,
+        instruction_error,
+        instruction_error_index,
+        custom_error: 0, // TODO: precompile error codes are not conformant, so we're ignoring custom error codes for now. This should be revisited in the future.
+        return_data: vec![],
+        executed_units: 0,
+        fee_details: None,
+        inner_instructions: vec![],
+        logs: vec![],
+        return_data_program_id: vec![],
+        epoch_boundary_accounts: vec![],
+        loaded_accounts_data_size: 0,
+        loaded_accounts_data_size_estimated: false,
+        loaded_accounts_data_size_limit: 0,
+        loaded_account_data_sizes: vec![],
+    }
+}
+
+/* Signs the transaction of `context` with the keypairs derived from
+`keypair_seeds`, replacing its signatures and message hash. Every required
+signer of the message must be derived from one of the seeds. */
+pub fn sign_transaction(context: &mut TxnContext, keypair_seeds: &[[u8; 32]]) -> Result<(), Error> {
+    let keypairs = keypair_seeds
+        .iter()
+        .map(|seed| keypair_from_seed(seed).map_err(|_| Error::InvalidFixtureInput))
+        .collect::<Result<Vec<Keypair>, Error>>()?;
+    let tx = context.tx.as_mut().ok_or(Error::InvalidFixtureInput)?;
+    let message = tx
+        .message
+        .as_ref()
+        .and_then(build_versioned_message)
+        .ok_or(Error::InvalidFixtureInput)?;
+
+    let message_data = message.serialize();
+    let num_required_signatures = message.header().num_required_signatures as usize;
+    tx.signatures = message
+        .static_account_keys()
+        .iter()
+        .take(num_required_signatures)
+        .map(|signer| {
+            keypairs
+                .iter()
+                .find(|keypair| keypair.pubkey() == *signer)
+                .map(|keypair| keypair.sign_message(&message_data).as_ref().to_vec())
+                .ok_or(Error::AccountMissing)
+        })
+        .collect::<Result<Vec<Vec<u8>>, Error>>()?;
+    tx.message_hash = message.hash().to_bytes().to_vec();
+    Ok(())
+}
+
+/* Fixture options of `process_transaction` */
+#[derive(Default)]
+pub(crate) struct TxnOptions {
+    /// Verify the transaction signatures like a validator does
+    pub(crate) verify_signatures: bool,
+    pub(crate) check_program_modification_slot: bool,
+    /// Report every loaded account in the resulting state
+    pub(crate) extended_resulting_state: bool,
+    /// Report the inner instructions, logs and return data program ID of
+    /// executed transactions
+    pub(crate) execution_details_enabled: bool,
+    /// Report the loaded accounts data size, its limit and the size each
+    /// account accounts for
+    pub(crate) loaded_accounts_data_size_enabled: bool,
+    /// Log messages are truncated beyond this many bytes. Defaults to the
+    /// log collector's limit, like a validator without
+    /// `--log-messages-bytes-limit`.
+    pub(crate) log_messages_bytes_limit: Option<usize>,
+}
+
+impl From<&TxnContext> for TxnOptions {
+    fn from(context: &TxnContext) -> Self {
+        TxnOptions {
+            verify_signatures: context.verify_signatures,
+            check_program_modification_slot: context.check_program_modification_slot,
+            extended_resulting_state: context.extended_resulting_state,
+            execution_details_enabled: context.execution_details_enabled,
+            loaded_accounts_data_size_enabled: context.loaded_accounts_data_size_enabled,
+            log_messages_bytes_limit: context.log_messages_bytes_limit.map(|limit| limit as usize),
+        }
+    }
+}
+
+/* All the accounts loaded for `message`, in order, followed by the
+programdata accounts of the upgradeable programs among them */
+fn loaded_accounts_to_proto(
+    bank: &Bank,
+    message: &SanitizedMessage,
+    accounts: &[TransactionAccount],
+) -> Vec<proto::LoadedAccount> {
+    let header = message.header();
+    let num_static_keys = message.static_account_keys().len();
+    let num_signed_keys = header.num_required_signatures as usize;
+    let num_writable_loaded_keys = match message {
+        SanitizedMessage::Legacy(_) => 0,
+        SanitizedMessage::V0(message) => message.loaded_addresses.writable.len(),
+    };
+    // Writability as requested by the message, before any demotion
+    let is_writable_requested = |index: usize| {
+        if index < num_signed_keys {
+            index < num_signed_keys.saturating_sub(header.num_readonly_signed_accounts as usize)
+        } else if index < num_static_keys {
+            index < num_static_keys.saturating_sub(header.num_readonly_unsigned_accounts as usize)
+        } else {
+            index - num_static_keys < num_writable_loaded_keys
+        }
     };
 
-    let sanitized_transaction = match bank.verify_transaction(
-        versioned_transaction,
-        TransactionVerificationMode::HashAndVerifyPrecompiles,
-    ) {
-        Ok(v) => v,
-        Err(e) => {
-            let (status, instruction_error, _custom_error, instruction_error_index) =
-                transaction_error_to_err_nums(&e);
-            return Some(TxnResult {
-                executed: false,
-                sanitization_error: true,
-                resulting_state: None,
-                rent: 0,
-                is_ok: false,
-                status,
-                instruction_error,
-                instruction_error_index,
-                custom_error: 0, // TODO: precompile error codes are not conformant, so we're ignoring custom error codes for now. This should be revisited in the future.
-                return_data: vec![],
-                executed_units: 0,
-                fee_details: None,
+    let mut loaded_accounts = accounts
+        .iter()
+        .enumerate()
+        .map(|(index, account)| proto::LoadedAccount {
+            state: Some(account.clone().into()),
+            is_writable_requested: is_writable_requested(index),
+            is_writable: message.is_writable(index),
+            is_signer: message.is_signer(index),
+        })
+        .collect::<Vec<proto::LoadedAccount>>();
+
+    let mut seen = accounts
+        .iter()
+        .map(|(pubkey, _)| *pubkey)
+        .collect::<HashSet<Pubkey>>();
+    for (_, account) in accounts {
+        if !bpf_loader_upgradeable::check_id(account.owner()) {
+            continue;
+        }
+        let Ok(UpgradeableLoaderState::Program {
+            programdata_address,
+        }) = bincode::deserialize(account.data())
+        else {
+            continue;
+        };
+        if !seen.insert(programdata_address) {
+            continue;
+        }
+        if let Some(programdata_account) = bank.get_account(&programdata_address) {
+            loaded_accounts.push(proto::LoadedAccount {
+                state: Some((programdata_address, programdata_account).into()),
+                is_writable_requested: false,
+                is_writable: false,
+                is_signer: false,
+            });
+        }
+    }
+    loaded_accounts
+}
+
+/* Size of the programdata account of an upgradeable program, 0 for other
+accounts */
+fn programdata_len(bank: &Bank, program_account: &AccountSharedData) -> usize {
+    if !bpf_loader_upgradeable::check_id(program_account.owner()) {
+        return 0;
+    }
+    match bincode::deserialize(program_account.data()) {
+        Ok(UpgradeableLoaderState::Program {
+            programdata_address,
+        }) => bank
+            .get_account(&programdata_address)
+            .map_or(0, |account| account.data().len()),
+        _ => 0,
+    }
+}
+
+/* Contribution of each account to the loaded accounts data size, following
+the account loader:
+- Every account of the message, by data size. Programs that are neither
+  instruction accounts nor writable are loaded from the program cache, whose
+  size includes the programdata account of upgradeable programs.
+- The loader of each invoked program, once per loader, unless it's the native
+  loader. */
+fn loaded_account_data_sizes(
+    bank: &Bank,
+    message: &SanitizedMessage,
+) -> Vec<proto::LoadedAccountDataSize> {
+    let instruction_accounts = message
+        .instructions()
+        .iter()
+        .flat_map(|instruction| instruction.accounts.iter().map(|index| *index as usize))
+        .collect::<HashSet<usize>>();
+
+    let mut sizes = message
+        .account_keys()
+        .iter()
+        .enumerate()
+        .map(|(index, pubkey)| {
+            let size = bank.get_account(pubkey).map_or(0, |account| {
+                let mut size = account.data().len();
+                if !instruction_accounts.contains(&index) && !message.is_writable(index) {
+                    size = size.saturating_add(programdata_len(bank, &account));
+                }
+                size
             });
+            proto::LoadedAccountDataSize {
+                address: pubkey.to_bytes().to_vec(),
+                size: size as u64,
+            }
+        })
+        .collect::<Vec<proto::LoadedAccountDataSize>>();
+
+    let mut loaders = HashSet::<Pubkey>::new();
+    for (program_id, _) in message.program_instructions_iter() {
+        if native_loader::check_id(program_id) {
+            continue;
         }
+        let Some(program_account) = bank.get_account(program_id) else {
+            continue;
+        };
+        let loader_id = *program_account.owner();
+        if native_loader::check_id(&loader_id) || !loaders.insert(loader_id) {
+            continue;
+        }
+        sizes.push(proto::LoadedAccountDataSize {
+            address: loader_id.to_bytes().to_vec(),
+            size: bank
+                .get_account(&loader_id)
+                .map_or(0, |account| account.data().len() as u64),
+        });
+    }
+    sizes
+}
+
+/* Sanitizes, loads and executes a single transaction against `bank`.
+If `commit` is set, the results are committed to the bank, so that
+subsequent transactions observe the resulting state. */
+pub(crate) fn process_transaction(
+    bank: &Bank,
+    tx: &proto::SanitizedTransaction,
+    commit: bool,
+    options: &TxnOptions,
+) -> Option<TxnResult> {
+    let account_keys = tx
+        .message
+        .as_ref()
+        .map(|message| message.account_keys.clone())
+        .unwrap_or_default();
+
+    // Malformed keys, hashes or signatures can't be sanitized
+    let Some(versioned_transaction) = build_versioned_transaction(tx, options.verify_signatures)
+    else {
+        return Some(sanitization_error_result(
+            &TransactionError::SanitizeFailure,
+        ));
+    };
+
+    let verification_mode = if options.verify_signatures {
+        TransactionVerificationMode::FullVerification
+    } else {
+        TransactionVerificationMode::HashAndVerifyPrecompiles
+    };
+    let sanitized_transaction =
+        match bank.verify_transaction(versioned_transaction, verification_mode) {
+            Ok(v) => v,
+            Err(e) => return Some(sanitization_error_result(&e)),
+        };
+
+    // Accounted before execution, as the account loader does
+    let loaded_account_data_sizes = if options.loaded_accounts_data_size_enabled {
+        loaded_account_data_sizes(bank, sanitized_transaction.message())
+    } else {
+        vec![]
     };
+    let loaded_accounts_data_size_limit = process_compute_budget_instructions(
+        sanitized_transaction
+            .message()
+            .program_instructions_iter()
+            .map(|(program_id, instruction)| (program_id, SVMInstruction::from(instruction))),
+    )
+    .map_or(0, |limits| limits.loaded_accounts_bytes.get() as u64);
 
     let transactions = [sanitized_transaction.clone()];
 
     let batch = bank.prepare_sanitized_batch(&transactions);
 
     let recording_config = ExecutionRecordingConfig {
-        enable_cpi_recording: false,
+        enable_cpi_recording: options.execution_details_enabled,
         enable_log_recording: true,
         enable_return_data_recording: true,
     };
@@ -555,11 +1135,11 @@ pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
     let configs = TransactionProcessingConfig {
         account_overrides: None,
         compute_budget: bank.compute_budget(),
-        log_messages_bytes_limit: None,
+        log_messages_bytes_limit: options.log_messages_bytes_limit,
         limit_to_load_programs: true,
         recording_config,
         transaction_account_lock_limit: None,
-        check_program_modification_slot: false,
+        check_program_modification_slot: options.check_program_modification_slot,
     };
 
     let mut metrics = TransactionErrorMetrics::default();
@@ -571,13 +1151,79 @@ pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
         configs,
     );
 
-    let mut txn_result: TxnResult = result.into();
+    let mut txn_result: TxnResult = (&result).into();
+    if options.loaded_accounts_data_size_enabled {
+        match &result.processing_results[0] {
+            Ok(ProcessedTransaction::Executed(executed_tx)) => {
+                txn_result.loaded_accounts_data_size =
+                    executed_tx.loaded_transaction.loaded_accounts_data_size as u64;
+            }
+            // Loading failed or wasn't attempted, so the account loader's
+            // counter isn't available
+            _ => {
+                txn_result.loaded_accounts_data_size = loaded_account_data_sizes
+                    .iter()
+                    .map(|account| account.size)
+                    .sum();
+                txn_result.loaded_accounts_data_size_estimated = true;
+            }
+        }
+        txn_result.loaded_accounts_data_size_limit = loaded_accounts_data_size_limit;
+        txn_result.loaded_account_data_sizes = loaded_account_data_sizes;
+    }
+    let rollback_state = match &result.processing_results[0] {
+        Ok(ProcessedTransaction::FeesOnly(fees_only_tx)) => Some(rollback_accounts_to_proto(
+            &fees_only_tx.rollback_accounts,
+            sanitized_transaction.fee_payer(),
+        )),
+        _ => None,
+    };
+    if let Ok(ProcessedTransaction::Executed(executed_tx)) = &result.processing_results[0] {
+        if options.execution_details_enabled {
+            let details = &executed_tx.execution_details;
+            txn_result.inner_instructions = details
+                .inner_instructions
+                .as_ref()
+                .map(inner_instructions_to_proto)
+                .unwrap_or_default();
+            txn_result.logs = details.log_messages.clone().unwrap_or_default();
+            txn_result.return_data_program_id = details
+                .return_data
+                .as_ref()
+                .map(|info| info.program_id.to_bytes().to_vec())
+                .unwrap_or_default();
+        }
+    }
+    let loaded_accounts = match &result.processing_results[0] {
+        Ok(ProcessedTransaction::Executed(executed_tx)) if options.extended_resulting_state => {
+            loaded_accounts_to_proto(
+                bank,
+                sanitized_transaction.message(),
+                &executed_tx.loaded_transaction.accounts,
+            )
+        }
+        _ => vec![],
+    };
+
+    if commit {
+        let LoadAndExecuteTransactionsOutput {
+            processing_results,
+            processed_counts,
+        } = result;
+        bank.commit_transactions(
+            &transactions,
+            processing_results,
+            &processed_counts,
+            &mut timings,
+        );
+    }
+
     if let Some(relevant_accounts) = &mut txn_result.resulting_state {
         let mut loaded_account_keys = HashSet::<Pubkey>::new();
         loaded_account_keys.extend(
             account_keys
                 .iter()
-                .map(|key| Pubkey::new_from_array(key.clone().try_into().ok().unwrap())),
+                .filter_map(|key| Pubkey::try_from(key.as_slice()).ok()),
         );
         match sanitized_transaction.message() {
             SanitizedMessage::Legacy(_) => {}
@@ -589,8 +1235,9 @@ pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
 
         // Only keep accounts that were passed in as account_keys or as ALUT accounts
         relevant_accounts.acct_states.retain(|account| {
-            let pubkey = Pubkey::new_from_array(account.address.clone().try_into().unwrap());
-            loaded_account_keys.contains(&pubkey) && pubkey != sysvar::instructions::id()
+            Pubkey::try_from(account.address.as_slice()).is_ok_and(|pubkey| {
+                loaded_account_keys.contains(&pubkey) && pubkey != sysvar::instructions::id()
+            })
         });
         relevant_accounts.acct_states = relevant_accounts
             .clone()
@@ -601,8 +1248,14 @@ pub fn execute_transaction(context: TxnContext) -> Option<TxnResult> {
             .map(|(_, account)| account)
             .collect();
 
+        relevant_accounts.loaded_accounts = loaded_accounts;
         txn_result.resulting_state = Some(relevant_accounts.clone());
     }
 
+    // Fee-only transactions still commit the fee payer and nonce accounts
+    if rollback_state.is_some() {
+        txn_result.resulting_state = rollback_state;
+    }
+
     Some(txn_result)
 }
@@ -0,0 +1,88 @@
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::AccountSharedData;
+use solana_sdk::transaction_context::TransactionAccount;
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// Fixture accounts, in input order, indexed by address.
+///
+/// Account data is shared (copy-on-write), so lookups and building the
+/// transaction accounts don't copy it. Lookups return the first account with
+/// a given address, like a linear search would.
+#[derive(Clone, Debug, Default)]
+pub struct AccountStore {
+    accounts: Vec<TransactionAccount>,
+    index: HashMap<Pubkey, usize>,
+}
+
+impl AccountStore {
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&AccountSharedData> {
+        self.index
+            .get(pubkey)
+            .map(|&position| &self.accounts[position].1)
+    }
+
+    pub fn contains(&self, pubkey: &Pubkey) -> bool {
+        self.index.contains_key(pubkey)
+    }
+
+    pub fn push(&mut self, pubkey: Pubkey, account: AccountSharedData) {
+        self.index.entry(pubkey).or_insert(self.accounts.len());
+        self.accounts.push((pubkey, account));
+    }
+}
+
+impl FromIterator<TransactionAccount> for AccountStore {
+    fn from_iter<I: IntoIterator<Item = TransactionAccount>>(iter: I) -> Self {
+        let mut store = AccountStore::default();
+        for (pubkey, account) in iter {
+            store.push(pubkey, account);
+        }
+        store
+    }
+}
+
+impl Deref for AccountStore {
+    type Target = [TransactionAccount];
+
+    fn deref(&self) -> &Self::Target {
+        &self.accounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_store() {
+        let owner = Pubkey::new_unique();
+        let first_key = Pubkey::new_unique();
+        let second_key = Pubkey::new_unique();
+        let first = AccountSharedData::new(1, 0, &owner);
+        let duplicate = AccountSharedData::new(2, 0, &owner);
+        let second = AccountSharedData::new(3, 0, &owner);
+
+        let mut store: AccountStore = vec![(first_key, first.clone())].into_iter().collect();
+        assert!(store.contains(&first_key));
+        assert!(!store.contains(&second_key));
+        assert_eq!(store.get(&second_key), None);
+
+        // Duplicate addresses are kept in input order, but lookups return
+        // the first account with the address
+        store.push(first_key, duplicate.clone());
+        store.push(second_key, second.clone());
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(&first_key), Some(&first));
+        assert_eq!(store.get(&second_key), Some(&second));
+        assert!(store.contains(&second_key));
+        assert_eq!(
+            store.to_vec(),
+            vec![
+                (first_key, first),
+                (first_key, duplicate),
+                (second_key, second)
+            ]
+        );
+    }
+}
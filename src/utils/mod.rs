@@ -1,3 +1,4 @@
+pub mod account_store;
 pub mod err_map;
 pub mod vm;
 use crate::proto;
@@ -31,29 +32,34 @@ lazy_static! {
 
 impl From<&proto::FeatureSet> for FeatureSet {
     fn from(input: &proto::FeatureSet) -> Self {
-        let mut feature_set = FeatureSet::default();
-        for id in &input.features {
-            if let Some(pubkey) = INDEXED_FEATURES.get(id) {
-                feature_set.activate(pubkey, 0);
+        crate::batch::feature_set(&input.features, || {
+            let mut feature_set = FeatureSet::default();
+            for id in &input.features {
+                if let Some(pubkey) = INDEXED_FEATURES.get(id) {
+                    feature_set.activate(pubkey, 0);
+                }
             }
-        }
-        feature_set
+            feature_set
+        })
     }
 }
 
-impl From<&AcctState> for AccountSharedData {
-    fn from(input: &AcctState) -> Self {
+impl TryFrom<&AcctState> for AccountSharedData {
+    type Error = crate::Error;
+
+    fn try_from(input: &AcctState) -> Result<Self, Self::Error> {
         // TODO: Can I move?
         let mut account_data = AccountSharedData::default();
         account_data.set_lamports(input.lamports);
         account_data.set_data_from_slice(input.data.as_slice());
-        account_data.set_owner(Pubkey::new_from_array(
-            input.owner.clone().try_into().unwrap(),
-        ));
+        account_data.set_owner(
+            Pubkey::try_from(input.owner.as_slice())
+                .map_err(|_| crate::Error::InvalidPubkeyBytes)?,
+        );
         account_data.set_executable(input.executable);
         account_data.set_rent_epoch(input.rent_epoch);
 
-        account_data
+        Ok(account_data)
     }
 }
 
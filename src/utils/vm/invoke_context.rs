@@ -0,0 +1,129 @@
+use crate::{fill_sysvar_cache, get_instr_accounts, load_builtins, proto::VmContext, InstrContext};
+use solana_compute_budget::compute_budget::ComputeBudget;
+use solana_log_collector::LogCollector;
+use solana_program_runtime::{
+    invoke_context::{BpfAllocator, EnvironmentConfig, InvokeContext, SyscallContext},
+    loaded_programs::ProgramCacheForTxBatch,
+    sysvar_cache::SysvarCache,
+};
+use solana_sdk::{
+    account::AccountSharedData,
+    pubkey::Pubkey,
+    rent::Rent,
+    transaction_context::{IndexOfAccount, TransactionAccount, TransactionContext},
+};
+use std::sync::Arc;
+
+/* State borrowed by the InvokeContext the VM harnesses run syscalls against.
+It is set up from the fixture's instruction context, like the instruction
+harness does. */
+pub struct InvokeContextSetup {
+    instr_ctx: InstrContext,
+    transaction_accounts: Vec<TransactionAccount>,
+    transaction_context: TransactionContext,
+    program_cache_for_tx_batch: ProgramCacheForTxBatch,
+    sysvar_cache: SysvarCache,
+    compute_budget: ComputeBudget,
+}
+
+impl InvokeContextSetup {
+    /* Adds an empty program account if the fixture doesn't provide one.
+    Returns None if a Core BPF program fails to load. */
+    pub fn new(mut instr_ctx: InstrContext) -> Option<Self> {
+        if !instr_ctx
+            .accounts
+            .contains(&instr_ctx.instruction.program_id)
+        {
+            instr_ctx.accounts.push(
+                instr_ctx.instruction.program_id,
+                AccountSharedData::default(),
+            );
+        }
+
+        let transaction_accounts: Vec<TransactionAccount> = instr_ctx.accounts.to_vec();
+        let compute_budget = ComputeBudget {
+            compute_unit_limit: instr_ctx.cu_avail,
+            ..ComputeBudget::default()
+        };
+        let transaction_context = TransactionContext::new(
+            transaction_accounts.clone(),
+            Rent::default(),
+            compute_budget.max_instruction_stack_depth,
+            compute_budget.max_instruction_trace_length,
+        );
+
+        let mut program_cache_for_tx_batch = ProgramCacheForTxBatch::default();
+        load_builtins(&mut program_cache_for_tx_batch, &instr_ctx)?;
+
+        let mut sysvar_cache = SysvarCache::default();
+        fill_sysvar_cache(&mut sysvar_cache, &instr_ctx);
+
+        Some(Self {
+            instr_ctx,
+            transaction_accounts,
+            transaction_context,
+            program_cache_for_tx_batch,
+            sysvar_cache,
+            compute_budget,
+        })
+    }
+
+    pub fn instr_ctx(&self) -> &InstrContext {
+        &self.instr_ctx
+    }
+
+    /* Creates the InvokeContext with the fixture's instruction pushed, and
+    the syscall context and return data set from vm_ctx. Returns None if the
+    return data program ID is invalid. */
+    pub fn invoke_context(&mut self, vm_ctx: &VmContext) -> Option<InvokeContext<'_>> {
+        let environment_config = EnvironmentConfig::new(
+            self.instr_ctx.last_blockhash,
+            None,
+            None,
+            Arc::new(self.instr_ctx.feature_set.clone()),
+            self.instr_ctx.lamports_per_signature,
+            &self.sysvar_cache,
+        );
+        let mut invoke_context = InvokeContext::new(
+            &mut self.transaction_context,
+            &mut self.program_cache_for_tx_batch,
+            environment_config,
+            Some(LogCollector::new_ref()),
+            self.compute_budget,
+        );
+
+        let instr = &self.instr_ctx.instruction;
+        let instr_accounts = get_instr_accounts(&self.transaction_accounts, &instr.accounts);
+        let program_idx_in_txn =
+            self.transaction_accounts
+                .iter()
+                .position(|(pubkey, _)| *pubkey == instr.program_id)? as IndexOfAccount;
+        invoke_context
+            .transaction_context
+            .get_next_instruction_context()
+            .unwrap()
+            .configure(
+                &[program_idx_in_txn],
+                instr_accounts.as_slice(),
+                &instr.data,
+            );
+        invoke_context.push().ok()?;
+
+        if let Some(return_data) = vm_ctx.return_data.clone() {
+            let program_id = Pubkey::try_from(return_data.program_id).ok()?;
+            invoke_context
+                .transaction_context
+                .set_return_data(program_id, return_data.data)
+                .ok()?;
+        }
+        invoke_context
+            .set_syscall_context(SyscallContext {
+                allocator: BpfAllocator::new(vm_ctx.heap_max),
+                accounts_metadata: vec![], // TODO: accounts metadata for direct mapping support
+                trace_log: Vec::new(),
+            })
+            .unwrap();
+
+        Some(invoke_context)
+    }
+}
@@ -1,6 +1,45 @@
+use solana_program_runtime::solana_rbpf::{
+    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion},
+    vm::{Config, ContextObject},
+};
+
 pub mod err_map;
+pub mod invoke_context;
 pub mod mem_regions;
 
 pub const STACK_SIZE: usize = 64 * STACK_GAP_SIZE as usize;
 pub const STACK_GAP_SIZE: u64 = 4_096;
 pub const HEAP_MAX: usize = 256 * 1024;
+
+/* Maps the VmContext sbpf_version field to an rbpf SBPF version. 0 is kept as
+V1 so fixtures predating the field retain their meaning. Versions the pinned
+rbpf doesn't know about are rejected. */
+pub fn sbpf_version(version: u32) -> Option<SBPFVersion> {
+    match version {
+        0 | 1 => Some(SBPFVersion::V1),
+        2 => Some(SBPFVersion::V2),
+        _ => None,
+    }
+}
+
+/* Rebuilds the loader to only enable `sbpf_version`. Executables are
+verified, compiled and executed with the loader's config, which otherwise
+enables the versions the feature set allows. */
+pub fn with_sbpf_version<C: ContextObject>(
+    loader: &BuiltinProgram<C>,
+    sbpf_version: SBPFVersion,
+) -> BuiltinProgram<C> {
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<C>>::default();
+    for (key, (name, function)) in loader.get_function_registry().iter() {
+        function_registry
+            .register_function(key, name, function)
+            .unwrap();
+    }
+    BuiltinProgram::new_loader(
+        Config {
+            enabled_sbpf_versions: sbpf_version..=sbpf_version,
+            ..loader.get_config().clone()
+        },
+        function_registry,
+    )
+}
@@ -1,42 +1,34 @@
 use crate::{
-    load_builtins,
     proto::{InstrEffects, SyscallContext, SyscallEffects},
     utils::{
         err_map::unpack_stable_result,
-        vm::{mem_regions, HEAP_MAX, STACK_SIZE},
+        vm::{invoke_context::InvokeContextSetup, mem_regions, sbpf_version, HEAP_MAX, STACK_SIZE},
     },
     InstrContext,
 };
 use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
 use solana_compute_budget::compute_budget::ComputeBudget;
-use solana_log_collector::LogCollector;
 use solana_program_runtime::{
-    invoke_context::{BpfAllocator, EnvironmentConfig, InvokeContext, SerializedAccountMetadata},
-    loaded_programs::ProgramCacheForTxBatch,
+    invoke_context::SerializedAccountMetadata,
     mem_pool::VmMemoryPool,
     solana_rbpf::{
         aligned_memory::AlignedMemory,
         ebpf,
         ebpf::HOST_ALIGN,
         memory_region::{MemoryMapping, MemoryRegion},
-        program::{BuiltinProgram, SBPFVersion},
+        program::BuiltinProgram,
         vm::{ContextObject, EbpfVm},
     },
-    sysvar_cache::SysvarCache,
 };
 use solana_sdk::{
-    account::{AccountSharedData, WritableAccount},
+    account::WritableAccount,
     instruction::InstructionError,
     pubkey::Pubkey,
-    rent::Rent,
-    transaction_context::{
-        IndexOfAccount, InstructionAccount, TransactionAccount, TransactionContext,
-    },
+    transaction_context::{IndexOfAccount, InstructionAccount, TransactionContext},
 };
-use std::sync::Arc;
 
 #[cfg(feature = "stub-agave")]
-use {prost::Message, std::ffi::c_int};
+use {crate::batch, prost::Message, std::ffi::c_int};
 
 // Requires "stub-agave" feature to be enabled
 // Similar to src/vm_syscalls.rs
@@ -69,49 +61,22 @@ pub unsafe extern "C" fn sol_compat_vm_cpi_syscall_v1(
     1
 }
 
+#[no_mangle]
+#[cfg(feature = "stub-agave")]
+pub unsafe extern "C" fn sol_compat_vm_cpi_syscall_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_vm_cpi_syscall)
+}
+
 // TODO: unify with other syscall harness after CPI fuzzing is stable
 #[allow(dead_code)]
 pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
-    let mut instr_ctx: InstrContext = input.instr_ctx?.try_into().ok()?;
-
-    let existing_pubkeys: Vec<_> = instr_ctx
-        .accounts
-        .iter()
-        .map(|(pubkey, _)| pubkey)
-        .collect();
-
-    if !existing_pubkeys.contains(&&instr_ctx.instruction.program_id) {
-        instr_ctx.accounts.push((
-            instr_ctx.instruction.program_id,
-            AccountSharedData::default().into(),
-        ));
-    }
-    // Create invoke context
-    // TODO: factor this into common code with lib.rs
-    let mut transaction_accounts =
-        Vec::<TransactionAccount>::with_capacity(instr_ctx.accounts.len() + 1);
-    #[allow(deprecated)]
-    instr_ctx
-        .accounts
-        .clone()
-        .into_iter()
-        .map(|(pubkey, account)| (pubkey, AccountSharedData::from(account)))
-        .for_each(|x| transaction_accounts.push(x));
-
-    let compute_budget = ComputeBudget {
-        compute_unit_limit: instr_ctx.cu_avail,
-        ..ComputeBudget::default()
-    };
-    let mut transaction_context = TransactionContext::new(
-        transaction_accounts.clone(),
-        Rent::default(),
-        compute_budget.max_instruction_stack_depth,
-        compute_budget.max_instruction_trace_length,
-    );
-
-    // sigh ... What is this mess?
-    let mut program_cache_for_tx_batch = ProgramCacheForTxBatch::default();
-    load_builtins(&mut program_cache_for_tx_batch);
+    let instr_ctx: InstrContext = input.instr_ctx?.try_into().ok()?;
+    let vm_ctx = input.vm_ctx?;
 
     let program_runtime_environment_v1 = create_program_runtime_environment_v1(
         &instr_ctx.feature_set,
@@ -122,62 +87,11 @@ pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
     .unwrap();
     let config = program_runtime_environment_v1.get_config();
 
-    let sysvar_cache = SysvarCache::default();
-    #[allow(deprecated)]
-    let (blockhash, lamports_per_signature) = sysvar_cache
-        .get_recent_blockhashes()
-        .ok()
-        .and_then(|x| (*x).last().cloned())
-        .map(|x| (x.blockhash, x.fee_calculator.lamports_per_signature))
-        .unwrap_or_default();
-
-    let environment_config = EnvironmentConfig::new(
-        blockhash,
-        None,
-        None,
-        Arc::new(instr_ctx.feature_set.clone()),
-        lamports_per_signature,
-        &sysvar_cache,
-    );
-    let log_collector = LogCollector::new_ref();
-    let mut invoke_context = InvokeContext::new(
-        &mut transaction_context,
-        &mut program_cache_for_tx_batch,
-        environment_config,
-        Some(log_collector.clone()),
-        compute_budget,
-    );
-
-    // Setup the instruction context in the invoke context
-    let instr = &instr_ctx.instruction;
-    let instr_accounts = crate::get_instr_accounts(&transaction_accounts, &instr.accounts);
-
-    let caller_instr_ctx = invoke_context
-        .transaction_context
-        .get_next_instruction_context()
-        .unwrap();
-
-    let program_idx_in_txn = transaction_accounts
-        .iter()
-        .position(|(pubkey, _)| *pubkey == instr_ctx.instruction.program_id)?
-        as IndexOfAccount;
-
-    caller_instr_ctx.configure(
-        &[program_idx_in_txn],
-        instr_accounts.as_slice(),
-        &instr.data,
-    );
-
-    // Push the invoke context. This sets up the instruction context trace, which is used in the CPI Syscall.
-    // Also pushes empty syscall context, which we will setup later
-    match invoke_context.push() {
-        Ok(_) => (),
-        Err(_) => eprintln!("Failed to push invoke context"),
-    }
-
-    // Setup syscall context in the invoke context
-    let vm_ctx = input.vm_ctx.unwrap();
-    let instr_accounts_len = instr_accounts.len();
+    // Create invoke context
+    let mut invoke_context_setup = InvokeContextSetup::new(instr_ctx)?;
+    let program_id = invoke_context_setup.instr_ctx().instruction.program_id;
+    let instr_accounts_len = invoke_context_setup.instr_ctx().instruction.accounts.len();
+    let mut invoke_context = invoke_context_setup.invoke_context(&vm_ctx)?;
 
     // Setup the CPI callback if there are exec effects
     #[cfg(feature = "stub-agave")]
@@ -198,22 +112,21 @@ pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
         ));
     }
 
+    // CPI translates the caller's account infos against one metadata entry per
+    // instruction account
     invoke_context
-        .set_syscall_context(solana_program_runtime::invoke_context::SyscallContext {
-            allocator: BpfAllocator::new(vm_ctx.heap_max),
-            accounts_metadata: vec![
-                SerializedAccountMetadata {
-                    original_data_len: 0,
-                    vm_data_addr: 0,
-                    vm_key_addr: 0,
-                    vm_owner_addr: 0,
-                    vm_lamports_addr: 0,
-                };
-                instr_accounts_len
-            ], // TODO: accounts metadata for direct mapping support
-            trace_log: Vec::new(),
-        })
-        .unwrap();
+        .get_syscall_context_mut()
+        .unwrap()
+        .accounts_metadata = vec![
+        SerializedAccountMetadata {
+            original_data_len: 0,
+            vm_data_addr: 0,
+            vm_key_addr: 0,
+            vm_owner_addr: 0,
+            vm_lamports_addr: 0,
+        };
+        instr_accounts_len
+    ]; // TODO: accounts metadata for direct mapping support
 
     // Set up memory mapping
     let syscall_inv = input.syscall_invocation.unwrap();
@@ -221,6 +134,7 @@ pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
     if vm_ctx.heap_max as usize > HEAP_MAX {
         return None;
     }
+    let sbpf_version = &sbpf_version(vm_ctx.sbpf_version)?;
 
     let mut mempool = VmMemoryPool::new();
     let rodata = AlignedMemory::<HOST_ALIGN>::from(&vm_ctx.rodata);
@@ -233,7 +147,7 @@ pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
         MemoryRegion::new_writable_gapped(
             stack.as_slice_mut(),
             ebpf::MM_STACK_START,
-            if config.enable_stack_frame_gaps {
+            if !sbpf_version.dynamic_stack_frames() && config.enable_stack_frame_gaps {
                 config.stack_frame_size as u64
             } else {
                 0
@@ -248,7 +162,7 @@ pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
         &vm_ctx.input_data_regions,
     );
 
-    let memory_mapping = match MemoryMapping::new(regions, config, &SBPFVersion::V1) {
+    let memory_mapping = match MemoryMapping::new(regions, config, sbpf_version) {
         Ok(mapping) => mapping,
         Err(_) => return None,
     };
@@ -257,7 +171,7 @@ pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
     let loader = std::sync::Arc::new(BuiltinProgram::new_mock());
     let mut vm = EbpfVm::new(
         loader,
-        &SBPFVersion::V1,
+        sbpf_version,
         &mut invoke_context,
         memory_mapping,
         STACK_SIZE,
@@ -286,7 +200,6 @@ pub fn execute_vm_cpi_syscall(input: SyscallContext) -> Option<SyscallEffects> {
 
     // Unwrap and return the effects of the syscall
     let program_result = vm.program_result;
-    let program_id = instr_ctx.instruction.program_id;
     let (error, error_kind, r0) =
         unpack_stable_result(program_result, vm.context_object_pointer, &program_id);
     Some(SyscallEffects {
@@ -1,8 +1,12 @@
 use crate::{
-    proto::{SyscallContext, SyscallEffects, VmContext},
+    batch,
+    proto::{SyscallContext, SyscallEffects, VmContext, VmInterpDiffReport, VmTraceEntry},
     utils::{
         pchash_inverse,
-        vm::{err_map, mem_regions, HEAP_MAX, STACK_SIZE},
+        vm::{
+            err_map, invoke_context::InvokeContextSetup, mem_regions, sbpf_version,
+            with_sbpf_version, HEAP_MAX, STACK_SIZE,
+        },
     },
     InstrContext,
 };
@@ -11,6 +15,7 @@ use prost::Message;
 use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
 use solana_compute_budget::compute_budget::ComputeBudget;
 use solana_program_runtime::{
+    invoke_context::InvokeContext,
     mem_pool::VmMemoryPool,
     solana_rbpf::{
         aligned_memory::AlignedMemory,
@@ -24,8 +29,8 @@ use solana_program_runtime::{
         vm::{Config, ContextObject, EbpfVm, TestContextObject},
     },
 };
-use solana_sdk::feature_set::bpf_account_data_direct_mapping;
-use std::{borrow::Borrow, ffi::c_int};
+use solana_sdk::feature_set::{bpf_account_data_direct_mapping, FeatureSet};
+use std::{ffi::c_int, sync::Arc};
 
 declare_builtin_function!(
     SyscallStub,
@@ -88,19 +93,94 @@ pub unsafe extern "C" fn sol_compat_vm_interp_v1(
     1
 }
 
+/* Differential mode: runs the program under both the interpreter and the
+JIT, and reports where the two diverge. Unlike `USE_INTERPRETER`, this is
+meant for fuzzing, so the known difference `USE_INTERPRETER` warns about is
+left out: the JIT doesn't account CUs at every instruction, so the CUs left
+by faulting programs may differ, and are only compared when both runs
+succeed. Any other divergence is an rbpf bug. */
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_vm_interp_jit_diff_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    let in_slice = std::slice::from_raw_parts(in_ptr, in_sz as usize);
+    let syscall_context = match SyscallContext::decode(in_slice) {
+        Ok(context) => context,
+        Err(_) => return 0,
+    };
+
+    let report = match execute_vm_interp_jit_diff(syscall_context) {
+        Some(v) => v,
+        None => return 0,
+    };
+    let out_slice = std::slice::from_raw_parts_mut(out_ptr, (*out_psz) as usize);
+    let out_vec = report.encode_to_vec();
+    if out_vec.len() > out_slice.len() {
+        return 0;
+    }
+    out_slice[..out_vec.len()].copy_from_slice(&out_vec);
+    *out_psz = out_vec.len() as u64;
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_vm_interp_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_vm_interp)
+}
+
 // We are actually executing the JIT-compiled program here
 pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffects> {
-    let instr_ctx: InstrContext = syscall_context.instr_ctx?.try_into().ok()?;
+    execute_vm(syscall_context, USE_INTERPRETER)
+}
+
+/* Returns None if the context is invalid */
+pub fn execute_vm_interp_jit_diff(syscall_context: SyscallContext) -> Option<VmInterpDiffReport> {
+    let interpreter_effects = execute_vm(syscall_context.clone(), true)?;
+    let jit_effects = execute_vm(syscall_context, false)?;
+
+    Some(VmInterpDiffReport {
+        r0_mismatch: interpreter_effects.r0 != jit_effects.r0,
+        error_mismatch: interpreter_effects.error != jit_effects.error,
+        pc_mismatch: interpreter_effects.pc != jit_effects.pc,
+        cu_avail_mismatch: interpreter_effects.error == 0
+            && jit_effects.error == 0
+            && interpreter_effects.cu_avail != jit_effects.cu_avail,
+        frame_count_mismatch: interpreter_effects.frame_count != jit_effects.frame_count,
+        heap_mismatch: interpreter_effects.heap != jit_effects.heap,
+        stack_mismatch: interpreter_effects.stack != jit_effects.stack,
+        input_data_regions_mismatch: interpreter_effects.input_data_regions
+            != jit_effects.input_data_regions,
+        interpreter_effects: Some(interpreter_effects),
+        jit_effects: Some(jit_effects),
+    })
+}
+
+fn execute_vm(syscall_context: SyscallContext, use_interpreter: bool) -> Option<SyscallEffects> {
+    if syscall_context.real_syscalls {
+        return execute_vm_with_syscalls(syscall_context, use_interpreter);
+    }
+
+    let instr_ctx: InstrContext = syscall_context.instr_ctx.clone()?.try_into().ok()?;
     let feature_set = instr_ctx.feature_set;
+    let sbpf_version = sbpf_version(syscall_context.vm_ctx.as_ref()?.sbpf_version)?;
 
     // Load default syscalls, to be stubbed later
-    let unstubbed_runtime = create_program_runtime_environment_v1(
+    let unstubbed_runtime = batch::program_runtime_environment_v1_for_sbpf_version(
         &feature_set,
         &ComputeBudget::default(),
         false,
         true, /* capture register state to obtain pc on success */
-    )
-    .unwrap();
+        sbpf_version,
+    );
 
     // stub syscalls
     let syscall_reg = unstubbed_runtime.get_function_registry();
@@ -114,21 +194,108 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
     let program_runtime_environment_v1 =
         BuiltinProgram::new_loader(unstubbed_runtime.get_config().clone(), stubbed_syscall_reg);
 
-    let sbpf_version: SBPFVersion = SBPFVersion::V1;
-    let loader = std::sync::Arc::new(program_runtime_environment_v1);
+    let loader = Arc::new(program_runtime_environment_v1);
 
     // Setup TestContextObject
     let mut context_obj = TestContextObject::new(instr_ctx.cu_avail);
 
+    run_vm(
+        &syscall_context,
+        &feature_set,
+        loader,
+        sbpf_version,
+        &mut context_obj,
+        use_interpreter,
+        Some(instr_ctx.cu_avail),
+    )
+}
+
+/* Runs the program with the real syscall registry, backed by an
+InvokeContext built from instr_ctx, like the syscall harness does. */
+fn execute_vm_with_syscalls(
+    syscall_context: SyscallContext,
+    use_interpreter: bool,
+) -> Option<SyscallEffects> {
+    let instr_ctx: InstrContext = syscall_context.instr_ctx.clone()?.try_into().ok()?;
+    let feature_set = instr_ctx.feature_set.clone();
+    let sbpf_version = sbpf_version(syscall_context.vm_ctx.as_ref()?.sbpf_version)?;
+
+    // Not taken from the batch cache: syscalls are invoked with this
+    // invocation's `InvokeContext` lifetime
+    let program_runtime_environment_v1 = create_program_runtime_environment_v1(
+        &feature_set,
+        &ComputeBudget::default(),
+        false,
+        true, /* capture register state to obtain pc on success */
+    )
+    .unwrap();
+
+    let mut invoke_context_setup = InvokeContextSetup::new(instr_ctx)?;
+    let mut invoke_context =
+        invoke_context_setup.invoke_context(syscall_context.vm_ctx.as_ref()?)?;
+
+    // The trace can't tell which steps syscalls charged CUs at
+    let mut effects = run_vm(
+        &syscall_context,
+        &feature_set,
+        Arc::new(with_sbpf_version(
+            &program_runtime_environment_v1,
+            sbpf_version,
+        )),
+        sbpf_version,
+        &mut invoke_context,
+        use_interpreter,
+        None,
+    )?;
+    effects.log = invoke_context
+        .get_log_collector()?
+        .borrow()
+        .get_recorded_content()
+        .join("\n")
+        .into_bytes();
+    Some(effects)
+}
+
+/* Context objects the harness runs programs against, exposing the
+instruction trace rbpf records into them. */
+trait TracingContextObject: ContextObject {
+    fn trace_log(&self) -> &[[u64; 12]];
+}
+
+impl TracingContextObject for TestContextObject {
+    fn trace_log(&self) -> &[[u64; 12]] {
+        &self.trace_log
+    }
+}
+
+impl TracingContextObject for InvokeContext<'_> {
+    fn trace_log(&self) -> &[[u64; 12]] {
+        self.get_syscall_context()
+            .map(|syscall_context| syscall_context.trace_log.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+fn run_vm<C: TracingContextObject>(
+    syscall_context: &SyscallContext,
+    feature_set: &FeatureSet,
+    loader: Arc<BuiltinProgram<C>>,
+    sbpf_version: SBPFVersion,
+    context_obj: &mut C,
+    use_interpreter: bool,
+    trace_cu_avail: Option<u64>,
+) -> Option<SyscallEffects> {
+    let max_trace_len = syscall_context.max_trace_len as usize;
+
     // setup memory
-    let vm_ctx = syscall_context.vm_ctx.unwrap();
+    let vm_ctx = syscall_context.vm_ctx.as_ref().unwrap();
     if vm_ctx.heap_max as usize > HEAP_MAX {
         return None;
     }
 
-    let function_registry = setup_internal_fn_registry(&vm_ctx);
+    let (text_pc, text) = text_section(vm_ctx)?;
 
-    let syscall_inv = syscall_context.syscall_invocation.unwrap();
+    let syscall_inv = syscall_context.syscall_invocation.as_ref().unwrap();
 
     let mut mempool = VmMemoryPool::new();
     let rodata = AlignedMemory::<HOST_ALIGN>::from(&vm_ctx.rodata);
@@ -138,7 +305,6 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
     /* TODO: should we just use loader.get_config()? */
     let config = &Config {
         aligned_memory_mapping: true,
-        enabled_sbpf_versions: SBPFVersion::V1..=SBPFVersion::V1,
         enable_stack_frame_gaps: !feature_set.is_active(&bpf_account_data_direct_mapping::id()),
         ..Config::default()
     };
@@ -148,7 +314,7 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
         MemoryRegion::new_writable_gapped(
             stack.as_slice_mut(),
             ebpf::MM_STACK_START,
-            if config.enable_stack_frame_gaps {
+            if !sbpf_version.dynamic_stack_frames() && config.enable_stack_frame_gaps {
                 config.stack_frame_size as u64
             } else {
                 0
@@ -172,7 +338,7 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
     let mut vm = EbpfVm::new(
         loader.clone(),
         &sbpf_version,
-        &mut context_obj,
+        context_obj,
         memory_mapping,
         STACK_SIZE,
     );
@@ -194,16 +360,31 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
     mem_regions::copy_memory_prefix(heap.as_slice_mut(), &syscall_inv.heap_prefix);
     mem_regions::copy_memory_prefix(stack.as_slice_mut(), &syscall_inv.stack_prefix);
 
-    let mut executable =
-        Executable::from_text_bytes(&vm_ctx.rodata, loader, sbpf_version, function_registry)
-            .unwrap();
-
-    if executable.verify::<RequisiteVerifier>().is_err() {
+    // Only the text section is verified, not the filler before it
+    let text_executable = Executable::from_text_bytes(
+        text,
+        loader.clone(),
+        sbpf_version,
+        setup_internal_fn_registry(vm_ctx, &sbpf_version, 0),
+    )
+    .unwrap();
+    if text_executable.verify::<RequisiteVerifier>().is_err() {
         return Some(SyscallEffects {
             error: -1,
             ..Default::default()
         });
     }
+    let mut executable = if text_pc == 0 {
+        text_executable
+    } else {
+        Executable::from_text_bytes(
+            &program_text(text_pc, text),
+            loader,
+            sbpf_version,
+            setup_internal_fn_registry(vm_ctx, &sbpf_version, text_pc),
+        )
+        .unwrap()
+    };
 
     if executable.jit_compile().is_err() {
         return Some(SyscallEffects {
@@ -214,19 +395,31 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
 
     let (_, result) = vm.execute_program(
         &executable,
-        USE_INTERPRETER, /* use JIT for fuzzing, interpreter for debugging */
+        use_interpreter, /* use JIT for fuzzing, interpreter for debugging */
     );
 
     if ENABLE_TRACE_DUMP {
-        eprintln!("Tracing: {:x?}", vm.context_object_pointer.trace_log);
+        eprintln!("Tracing: {:x?}", vm.context_object_pointer.trace_log());
     }
+    let trace = build_trace(
+        vm.context_object_pointer.trace_log(),
+        text_pc,
+        text,
+        trace_cu_avail,
+        max_trace_len,
+    );
 
+    // A callx into the filler executed one filler instruction, which a
+    // loaded ELF doesn't
+    let called_outside_text = (vm.registers[11] as usize) < text_pc;
     let result = match result {
-        StableResult::Err(err) => StableResult::Err(process_result(&mut vm, &executable, err)),
+        StableResult::Err(err) => {
+            StableResult::Err(process_result(&mut vm, &executable, err, text_pc))
+        }
         StableResult::Ok(n) => StableResult::Ok(n),
     };
 
-    if let StableResult::Err(err) = result.borrow() {
+    if let StableResult::Err(err) = &result {
         if let EbpfError::ExceededMaxInstructions = err {
             /* CU error is difficult to properly compare as there may have been
             valid writes to the memory regions prior to capturing the error. And
@@ -236,6 +429,7 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
                 error: err_map::get_fd_vm_err_code(err).into(),
                 cu_avail: 0,
                 frame_count: vm.call_depth,
+                trace,
                 ..Default::default()
             });
         }
@@ -250,7 +444,10 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
             StableResult::Ok(n) => n,
             StableResult::Err(_) => 0,
         },
-        cu_avail: vm.context_object_pointer.get_remaining(),
+        cu_avail: vm
+            .context_object_pointer
+            .get_remaining()
+            .saturating_add(called_outside_text as u64),
         frame_count: vm.call_depth,
         heap: heap.as_slice().into(),
         stack: stack.as_slice().into(),
@@ -258,20 +455,96 @@ pub fn execute_vm_interp(syscall_context: SyscallContext) -> Option<SyscallEffec
         input_data_regions: mem_regions::extract_input_data_regions(&vm.memory_mapping),
         log: vec![],
         pc: match result {
-            StableResult::Ok(_) => match vm.context_object_pointer.trace_log.last() {
+            StableResult::Ok(_) => match vm.context_object_pointer.trace_log().last() {
                 Some(regs) => regs[11],
                 None => vm.registers[11],
             },
             StableResult::Err(_) => vm.registers[11],
-        },
-        ..Default::default() // FIXME: implement rodata
+        }
+        .saturating_sub(text_pc as u64),
+        trace,
+        ..Default::default()
     })
 }
 
+/* The text section within rodata, as reported by the ELF loader, along with
+the pc of its first instruction within rodata. Programs read constants from
+the whole rodata region, but only the text section is verified and executed.
+Fixtures without a text section length execute the whole of rodata. Returns
+None if the text section is out of bounds or misaligned. */
+fn text_section(vm_ctx: &VmContext) -> Option<(usize, &[u8])> {
+    if vm_ctx.rodata_text_section_length == 0 {
+        return Some((0, &vm_ctx.rodata));
+    }
+    let text_off = vm_ctx.rodata_text_section_offset as usize;
+    let text_len = vm_ctx.rodata_text_section_length as usize;
+    if text_off % ebpf::INSN_SIZE != 0 {
+        return None;
+    }
+    let text = vm_ctx
+        .rodata
+        .get(text_off..text_off.checked_add(text_len)?)?;
+    Some((text_off / ebpf::INSN_SIZE, text))
+}
+
+/* rbpf maps the text of an executable built from text bytes at the start of
+the program region, and resolves callx targets relative to it. So that they
+resolve relative to the text section's vaddr (MM_PROGRAM_START + its offset
+in rodata), the text section is preceded by `text_pc` filler instructions.
+
+Each filler instruction calls itself, which is never a registered function,
+so a callx to rodata before the text section faults there. `process_result`
+reports that as CallOutsideTextSegment, like for a loaded ELF. */
+fn program_text(text_pc: usize, text: &[u8]) -> Vec<u8> {
+    let filler = [ebpf::CALL_IMM, 0x10, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]; // call -1
+    let mut program_text = filler.repeat(text_pc);
+    program_text.extend_from_slice(text);
+    program_text
+}
+
+/* Converts the first `max_len` steps of the instruction trace. The trace is
+always recorded, as the loader is created with debugging features, which
+also capture the pc on success.
+
+With stubbed syscalls, which consume no CUs, each step consumes a single CU
+and the CUs available at each step follow from the initial `cu_avail`.
+Entries carry no CU count when `cu_avail` is None. */
+fn build_trace(
+    trace_log: &[[u64; 12]],
+    text_pc: usize,
+    text: &[u8],
+    cu_avail: Option<u64>,
+    max_len: usize,
+) -> Vec<VmTraceEntry> {
+    trace_log
+        .iter()
+        // Filler instructions aren't part of the program
+        .filter(|registers| registers[11] as usize >= text_pc)
+        .take(max_len)
+        .enumerate()
+        .map(|(step, registers)| {
+            let mut registers = *registers;
+            registers[11] -= text_pc as u64;
+            let pc = registers[11];
+            VmTraceEntry {
+                pc,
+                opcode: text
+                    .get((pc as usize).saturating_mul(ebpf::INSN_SIZE))
+                    .copied()
+                    .unwrap_or_default() as u32,
+                registers: registers.to_vec(),
+                cu_avail: cu_avail.map(|cu_avail| cu_avail.saturating_sub(step as u64)),
+            }
+        })
+        .collect()
+}
+
 /* This sets up a function registry based on a Firedancer-loaded SBPF program.
 The key difference is call immediates are hashed based on their target pc,
 not the function symbol. Firedancer maintains a bit vector of all valid call
 destinations[1], which the interpreter uses during the CALL_IMM instruction.
+SBPF versions with static syscalls use pc-relative calls instead, and rbpf
+looks those up by the target pc itself, so that is the key registered there.
 
 To mimic that behavior here, we iterate through the valid call destinations
 in vm_ctx.call_whitelist, and register the pc hash as an entry in the registry.
@@ -282,15 +555,23 @@ a hashmap, while FD's bit vector is a simple array. Out of bounds queries are
 non-issue here, but require explicit handling in FD. This causes a slight
 difference in error checks in CALL_IMM, which we handle in process_result.
 
+Functions are registered at their pc plus `text_pc`, the number of filler
+instructions preceding the text section in the executable (see
+`program_text`). Hashed keys are of the pc within the text section.
+
 [1](https://github.com/firedancer-io/firedancer/blob/93cea434dfe2f728f2ab4746590972644c06b863/src/ballet/sbpf/fd_sbpf_loader.h#L27). */
-fn setup_internal_fn_registry(vm_ctx: &VmContext) -> FunctionRegistry<usize> {
+fn setup_internal_fn_registry(
+    vm_ctx: &VmContext,
+    sbpf_version: &SBPFVersion,
+    text_pc: usize,
+) -> FunctionRegistry<usize> {
     let mut fn_reg = FunctionRegistry::default();
 
     // register entry point
     let _ = fn_reg.register_function(
         ebpf::hash_symbol_name(b"entrypoint"),
         b"entrypoint",
-        vm_ctx.entry_pc as usize,
+        vm_ctx.entry_pc as usize + text_pc,
     );
 
     let call_whitelist = &vm_ctx.call_whitelist;
@@ -298,11 +579,12 @@ fn setup_internal_fn_registry(vm_ctx: &VmContext) -> FunctionRegistry<usize> {
         for bit_idx in 0..8 {
             if (byte & (1 << bit_idx)) != 0 {
                 let pc = byte_idx * 8 + bit_idx;
-                let _ = fn_reg.register_function(
-                    ebpf::hash_symbol_name(&u64::to_le_bytes(pc as u64)),
-                    b"fn",
-                    pc,
-                );
+                let key = if sbpf_version.static_syscalls() {
+                    (pc + text_pc) as u32
+                } else {
+                    ebpf::hash_symbol_name(&u64::to_le_bytes(pc as u64))
+                };
+                let _ = fn_reg.register_function(key, b"fn", pc + text_pc);
             }
         }
     }
@@ -311,11 +593,22 @@ fn setup_internal_fn_registry(vm_ctx: &VmContext) -> FunctionRegistry<usize> {
 }
 
 /* Look through errors, and map to something else if necessary */
-fn process_result<C: ContextObject>(
+fn process_result<C: TracingContextObject>(
     vm: &mut EbpfVm<C>,
     executable: &Executable<C>,
     err: EbpfError,
+    text_pc: usize,
 ) -> EbpfError {
+    if (vm.registers[11] as usize) < text_pc {
+        /* A callx to rodata before the text section faulted in the filler
+        (see `program_text`). rbpf rejects the callx itself for a loaded ELF,
+        so report the pc of the callx, the step before the filler. */
+        let trace_log = vm.context_object_pointer.trace_log();
+        if let Some(registers) = trace_log.len().checked_sub(2).map(|i| trace_log[i]) {
+            vm.registers[11] = registers[11];
+        }
+        return EbpfError::CallOutsideTextSegment;
+    }
     match err {
         EbpfError::UnsupportedInstruction => {
             /* CALL_IMM throws UnsupportedInstruction iff the immediate
@@ -331,10 +624,17 @@ fn process_result<C: ContextObject>(
             let pc = vm.registers[11];
             let insn = ebpf::get_insn_unchecked(executable.get_text_bytes().1, pc as usize);
             if insn.opc == ebpf::CALL_IMM {
-                let pchash = insn.imm as u32;
-                if pchash_inverse(pchash)
-                    > (executable.get_text_bytes().1.len() / ebpf::INSN_SIZE) as u32
-                {
+                // Within the text section
+                let target_pc = if executable.get_sbpf_version().static_syscalls() {
+                    (pc as i64)
+                        .wrapping_add(insn.imm)
+                        .wrapping_add(1)
+                        .wrapping_sub(text_pc as i64) as u32
+                } else {
+                    pchash_inverse(insn.imm as u32)
+                };
+                let text_len = executable.get_text_bytes().1.len() / ebpf::INSN_SIZE - text_pc;
+                if target_pc > text_len as u32 {
                     // need to simulate pushing a stack frame
                     vm.call_depth += 1;
                     EbpfError::CallOutsideTextSegment
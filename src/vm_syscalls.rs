@@ -1,8 +1,10 @@
 use crate::{
-    load_builtins,
+    batch,
     proto::{SyscallContext, SyscallEffects},
     utils::err_map::unpack_stable_result,
+    utils::vm::invoke_context::InvokeContextSetup,
     utils::vm::mem_regions,
+    utils::vm::sbpf_version,
     utils::vm::HEAP_MAX,
     utils::vm::STACK_SIZE,
     InstrContext,
@@ -10,32 +12,19 @@ use crate::{
 use prost::Message;
 use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
 use solana_compute_budget::compute_budget::ComputeBudget;
-use solana_log_collector::LogCollector;
-use solana_program_runtime::sysvar_cache::SysvarCache;
-use solana_program_runtime::{invoke_context::EnvironmentConfig, solana_rbpf::vm::ContextObject};
+use solana_program_runtime::solana_rbpf::vm::ContextObject;
 use solana_program_runtime::{
-    invoke_context::InvokeContext,
-    loaded_programs::ProgramCacheForTxBatch,
     mem_pool::VmMemoryPool,
     solana_rbpf::{
         aligned_memory::AlignedMemory,
         ebpf,
         ebpf::HOST_ALIGN,
         memory_region::{MemoryMapping, MemoryRegion},
-        program::{BuiltinProgram, SBPFVersion},
+        program::BuiltinProgram,
         vm::EbpfVm,
     },
 };
-use solana_sdk::transaction_context::{TransactionAccount, TransactionContext};
-use solana_sdk::{
-    account::AccountSharedData,
-    clock::Clock,
-    epoch_schedule::EpochSchedule,
-    rent::Rent,
-    sysvar::{last_restart_slot, SysvarId},
-};
-use solana_sdk::{pubkey::Pubkey, transaction_context::IndexOfAccount};
-use std::{ffi::c_int, sync::Arc};
+use std::ffi::c_int;
 
 #[no_mangle]
 pub unsafe extern "C" fn sol_compat_vm_syscall_execute_v1(
@@ -65,161 +54,38 @@ pub unsafe extern "C" fn sol_compat_vm_syscall_execute_v1(
     1
 }
 
-pub fn execute_vm_syscall(input: SyscallContext) -> Option<SyscallEffects> {
-    let mut instr_ctx: InstrContext = input.instr_ctx?.try_into().ok()?;
-
-    let existing_pubkeys: Vec<_> = instr_ctx
-        .accounts
-        .iter()
-        .map(|(pubkey, _)| pubkey)
-        .collect();
-
-    if !existing_pubkeys.contains(&&instr_ctx.instruction.program_id) {
-        instr_ctx.accounts.push((
-            instr_ctx.instruction.program_id,
-            AccountSharedData::default().into(),
-        ));
-    }
-
-    let feature_set = instr_ctx.feature_set;
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_vm_syscall_execute_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_vm_syscall)
+}
 
-    let program_runtime_environment_v1 =
-        create_program_runtime_environment_v1(&feature_set, &ComputeBudget::default(), true, false)
-            .unwrap();
+pub fn execute_vm_syscall(input: SyscallContext) -> Option<SyscallEffects> {
+    let instr_ctx: InstrContext = input.instr_ctx?.try_into().ok()?;
+    let vm_ctx = input.vm_ctx?;
+
+    // Not taken from the batch cache: syscalls are invoked with this
+    // invocation's `InvokeContext` lifetime
+    let program_runtime_environment_v1 = create_program_runtime_environment_v1(
+        &instr_ctx.feature_set,
+        &ComputeBudget::default(),
+        true,
+        false,
+    )
+    .unwrap();
     let config = program_runtime_environment_v1.get_config();
 
     // Create invoke context
-    // TODO: factor this into common code with lib.rs
-    let mut transaction_accounts =
-        Vec::<TransactionAccount>::with_capacity(instr_ctx.accounts.len() + 1);
-    #[allow(deprecated)]
-    instr_ctx
-        .accounts
-        .clone()
-        .into_iter()
-        .map(|(pubkey, account)| (pubkey, AccountSharedData::from(account)))
-        .for_each(|x| transaction_accounts.push(x));
-
-    let compute_budget = ComputeBudget {
-        compute_unit_limit: instr_ctx.cu_avail,
-        ..ComputeBudget::default()
-    };
-    let mut transaction_context = TransactionContext::new(
-        transaction_accounts.clone(),
-        Rent::default(),
-        compute_budget.max_instruction_stack_depth,
-        compute_budget.max_instruction_trace_length,
-    );
-
-    if let Some(vm_ctx) = &input.vm_ctx {
-        if let Some(return_data) = vm_ctx.return_data.clone() {
-            let program_id = Pubkey::try_from(return_data.program_id).unwrap();
-            transaction_context
-                .set_return_data(program_id, return_data.data)
-                .unwrap();
-        }
-    }
-
-    // sigh ... What is this mess?
-    let mut program_cache_for_tx_batch = ProgramCacheForTxBatch::default();
-    load_builtins(&mut program_cache_for_tx_batch);
-
-    let mut sysvar_cache = SysvarCache::default();
-
-    sysvar_cache.fill_missing_entries(|pubkey, callbackback| {
-        if let Some(account) = instr_ctx.accounts.iter().find(|(key, _)| key == pubkey) {
-            if account.1.lamports > 0 {
-                callbackback(&account.1.data);
-            }
-        }
-    });
-
-    // Any default values for missing sysvar values should be set here
-    sysvar_cache.fill_missing_entries(|pubkey, callbackback| {
-        if *pubkey == Clock::id() {
-            // Set the default clock slot to something arbitrary beyond 0
-            // This prevents DelayedVisibility errors when executing BPF programs
-            let default_clock = Clock {
-                slot: 10,
-                ..Default::default()
-            };
-            let clock_data = bincode::serialize(&default_clock).unwrap();
-            callbackback(&clock_data);
-        }
-        if *pubkey == EpochSchedule::id() {
-            callbackback(&bincode::serialize(&EpochSchedule::default()).unwrap());
-        }
-        if *pubkey == Rent::id() {
-            callbackback(&bincode::serialize(&Rent::default()).unwrap());
-        }
-        if *pubkey == last_restart_slot::id() {
-            let slot_val = 5000_u64;
-            callbackback(&bincode::serialize(&slot_val).unwrap());
-        }
-    });
-
-    #[allow(deprecated)]
-    let (blockhash, lamports_per_signature) = sysvar_cache
-        .get_recent_blockhashes()
-        .ok()
-        .and_then(|x| (*x).last().cloned())
-        .map(|x| (x.blockhash, x.fee_calculator.lamports_per_signature))
-        .unwrap_or_default();
-
-    let environment_config = EnvironmentConfig::new(
-        blockhash,
-        None,
-        None,
-        Arc::new(feature_set.clone()),
-        lamports_per_signature,
-        &sysvar_cache,
-    );
-    let log_collector = LogCollector::new_ref();
-    let mut invoke_context = InvokeContext::new(
-        &mut transaction_context,
-        &mut program_cache_for_tx_batch,
-        environment_config,
-        Some(log_collector.clone()),
-        compute_budget,
-    );
-
-    let instr = &instr_ctx.instruction;
-    let instr_accounts = crate::get_instr_accounts(&transaction_accounts, &instr.accounts);
-
-    let caller_instr_ctx = invoke_context
-        .transaction_context
-        .get_next_instruction_context()
-        .unwrap();
-
-    let program_idx_in_txn = transaction_accounts
-        .iter()
-        .position(|(pubkey, _)| *pubkey == instr_ctx.instruction.program_id)?
-        as IndexOfAccount;
-
-    caller_instr_ctx.configure(
-        &[program_idx_in_txn],
-        instr_accounts.as_slice(),
-        &instr.data,
-    );
-
-    match invoke_context.push() {
-        Ok(_) => (),
-        Err(_) => eprintln!("Failed to push invoke context"),
-    }
-    invoke_context
-        .set_syscall_context(solana_program_runtime::invoke_context::SyscallContext {
-            allocator: solana_program_runtime::invoke_context::BpfAllocator::new(
-                input.vm_ctx.clone().unwrap().heap_max,
-            ),
-            accounts_metadata: vec![], // TODO: accounts metadata for direct mapping support
-            trace_log: Vec::new(),
-        })
-        .unwrap();
-    // TODO: support different versions
-    let sbpf_version = &SBPFVersion::V1;
+    let mut invoke_context_setup = InvokeContextSetup::new(instr_ctx)?;
+    let program_id = invoke_context_setup.instr_ctx().instruction.program_id;
+    let mut invoke_context = invoke_context_setup.invoke_context(&vm_ctx)?;
 
     // Set up memory mapping
-    let vm_ctx = input.vm_ctx.unwrap();
+    let sbpf_version = &sbpf_version(vm_ctx.sbpf_version)?;
     // Follow FD harness behavior
     if vm_ctx.heap_max as usize > HEAP_MAX {
         return None;
@@ -277,7 +143,7 @@ pub fn execute_vm_syscall(input: SyscallContext) -> Option<SyscallEffects> {
     let loader = std::sync::Arc::new(BuiltinProgram::new_mock());
     let mut vm = EbpfVm::new(
         loader,
-        &SBPFVersion::V1,
+        sbpf_version,
         &mut invoke_context,
         memory_mapping,
         STACK_SIZE,
@@ -309,7 +175,6 @@ pub fn execute_vm_syscall(input: SyscallContext) -> Option<SyscallEffects> {
     vm.invoke_function(syscall_func);
 
     // Unwrap and return the effects of the syscall
-    let program_id = instr_ctx.instruction.program_id;
     let program_result = vm.program_result;
     let (error, error_kind, r0) =
         unpack_stable_result(program_result, vm.context_object_pointer, &program_id);
@@ -333,5 +198,6 @@ pub fn execute_vm_syscall(input: SyscallContext) -> Option<SyscallEffects> {
             .join("\n")
             .into_bytes(),
         pc: 0,
+        trace: vec![],
     })
 }
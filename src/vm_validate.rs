@@ -1,7 +1,7 @@
+use crate::batch;
 use crate::elf_loader::ACTIVATE_FEATURES;
 use crate::proto::{FullVmContext, ValidateVmEffects};
 use prost::Message;
-use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
 use solana_compute_budget::compute_budget::ComputeBudget;
 use solana_program_runtime::solana_rbpf::elf::Executable;
 use solana_program_runtime::solana_rbpf::error::EbpfError;
@@ -52,17 +52,16 @@ fn gen_feature_set() -> FeatureSet {
 }
 
 pub fn validate_vm_text(text_bytes: &[u8], feature_set: &FeatureSet) -> Option<ValidateVmEffects> {
-    let program_runtime_environment_v1 = create_program_runtime_environment_v1(
+    let program_runtime_environment_v1 = batch::program_runtime_environment_v1(
         feature_set,
         &ComputeBudget::default(),
         false, // doesn't matter since bytes are "loaded"
         false, // doesn't matter
-    )
-    .unwrap();
+    );
 
     let exec = match Executable::new_from_text_bytes(
         text_bytes,
-        std::sync::Arc::new(program_runtime_environment_v1),
+        program_runtime_environment_v1,
         solana_program_runtime::solana_rbpf::program::SBPFVersion::V1,
         FunctionRegistry::default(),
     ) {
@@ -108,6 +107,16 @@ pub unsafe extern "C" fn sol_compat_vm_validate_v1(
     1
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn sol_compat_vm_validate_batch_v1(
+    out_ptr: *mut u8,
+    out_psz: *mut u64,
+    in_ptr: *mut u8,
+    in_sz: u64,
+) -> c_int {
+    batch::execute_batch(out_ptr, out_psz, in_ptr, in_sz, execute_vm_validate)
+}
+
 pub fn execute_vm_validate(input: FullVmContext) -> Option<ValidateVmEffects> {
     let vm_ctx = match input.vm_ctx {
         Some(vm_ctx) => vm_ctx,
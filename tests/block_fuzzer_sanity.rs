@@ -0,0 +1,126 @@
+use prost::Message;
+use solana_program::hash::Hash;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::feature_set::*;
+use solana_sdk::signature::Signature;
+use solfuzz_agave::block_fuzzer::sol_compat_block_execute_v1;
+use solfuzz_agave::proto::{
+    AcctState, BlockContext, BlockEffects, BlockSlot, CompiledInstruction, EpochContext,
+    FeatureSet, MessageHeader, SanitizedTransaction, TransactionMessage,
+};
+use solfuzz_agave::{feature_list, utils::feature_u64, HARDCODED_FEATURES};
+
+fn get_features() -> FeatureSet {
+    let additional_features = feature_list![set_exempt_rent_epoch_max];
+    let mut features = FeatureSet::default();
+
+    features.features = HARDCODED_FEATURES.into();
+    features.features.extend_from_slice(additional_features);
+    features
+}
+
+fn system_transfer(fee_payer: &Pubkey, recipient: &Pubkey, lamports: u64) -> SanitizedTransaction {
+    let mut data = vec![2, 0, 0, 0];
+    data.extend_from_slice(&lamports.to_le_bytes());
+
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        }),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            recipient.to_bytes().to_vec(),
+            vec![0; 32],
+        ],
+        account_shared_data: vec![],
+        recent_blockhash: vec![],
+        instructions: vec![CompiledInstruction {
+            program_id_index: 2,
+            accounts: vec![0, 1],
+            data,
+        }],
+        address_table_lookups: vec![],
+    };
+
+    SanitizedTransaction {
+        message: Some(message),
+        message_hash: Hash::new_unique().to_bytes().to_vec(),
+        is_simple_vote_tx: false,
+        signatures: vec![Signature::new_unique().as_ref().to_vec()],
+    }
+}
+
+#[test]
+fn test_block_state_carries_over() {
+    let fee_payer = Pubkey::new_unique();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 10000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+
+    let recipient = Pubkey::new_unique();
+    let recipient_data = AcctState {
+        address: recipient.to_bytes().to_vec(),
+        lamports: 900000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+
+    let block_input = BlockContext {
+        slots: vec![
+            BlockSlot {
+                slot: 20,
+                blockhash: vec![],
+                txns: vec![system_transfer(&fee_payer, &recipient, 10)],
+            },
+            BlockSlot {
+                slot: 21,
+                blockhash: Hash::new_unique().to_bytes().to_vec(),
+                txns: vec![system_transfer(&fee_payer, &recipient, 20)],
+            },
+        ],
+        acct_states: vec![fee_payer_data, recipient_data],
+        blockhash_queue: vec![],
+        epoch_ctx: Some(EpochContext {
+            features: Some(get_features()),
+        }),
+    };
+
+    let mut buffer: Vec<u8> = block_input.encode_to_vec();
+    let buffer_len = buffer.len() as u64;
+
+    let mut res_buffer: Vec<u8> = vec![0; 4096];
+    let mut res_buffer_len = res_buffer.len() as u64;
+    let res = unsafe {
+        sol_compat_block_execute_v1(
+            res_buffer.as_mut_ptr(),
+            &mut res_buffer_len,
+            buffer.as_mut_ptr(),
+            buffer_len,
+        )
+    };
+
+    assert_eq!(res, 1);
+    let effects = BlockEffects::decode(&res_buffer[..res_buffer_len as usize]).unwrap();
+    assert_eq!(effects.txn_results.len(), 2);
+    assert!(effects.txn_results.iter().all(|result| result.is_ok));
+
+    // The second transfer must observe the first one
+    let recipient_state = effects
+        .acct_states
+        .iter()
+        .find(|item| item.address == recipient.to_bytes())
+        .unwrap();
+    assert_eq!(recipient_state.lamports, 900030);
+}
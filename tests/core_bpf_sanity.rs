@@ -0,0 +1,147 @@
+#[cfg(not(feature = "core-bpf"))]
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::pubkey::Pubkey;
+use solfuzz_agave::core_bpf::{
+    clear_core_bpf_program, is_core_bpf_program, set_core_bpf_program, CORE_BPF_PROGRAMS_ENV,
+};
+#[cfg(not(feature = "core-bpf"))]
+use solfuzz_agave::core_bpf_diff::execute_instr_core_bpf_diff;
+#[cfg(not(feature = "core-bpf"))]
+use solfuzz_agave::execute_instr_proto;
+#[cfg(not(feature = "core-bpf"))]
+use solfuzz_agave::proto::{AcctState, InstrAcct, InstrContext};
+use solfuzz_agave::sol_compat_init;
+#[cfg(not(feature = "core-bpf"))]
+use solfuzz_agave::utils::err_map::instr_err_to_num;
+use std::env;
+
+const ELF_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/simple_transfer_program.so"
+);
+
+#[test]
+fn test_set_and_clear_core_bpf_program() {
+    let program_id = Pubkey::new_unique();
+    let elf = std::fs::read(ELF_PATH).unwrap();
+
+    // An ELF that doesn't load leaves the configuration untouched
+    assert!(!set_core_bpf_program(program_id, vec![0; 64]));
+    assert!(!is_core_bpf_program(&program_id));
+
+    assert!(set_core_bpf_program(program_id, elf));
+    assert!(is_core_bpf_program(&program_id));
+
+    clear_core_bpf_program(&program_id);
+    assert!(!is_core_bpf_program(&program_id));
+}
+
+#[test]
+fn test_core_bpf_programs_env() {
+    // Both cases share the environment variable, so they run sequentially
+    let program_ids = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let programs = program_ids
+        .iter()
+        .map(|program_id| format!("{}:{}", program_id, ELF_PATH))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    env::set_var(CORE_BPF_PROGRAMS_ENV, programs);
+    assert_eq!(unsafe { sol_compat_init(0) }, 1);
+    for program_id in &program_ids {
+        assert!(is_core_bpf_program(program_id));
+        clear_core_bpf_program(program_id);
+    }
+
+    env::set_var(CORE_BPF_PROGRAMS_ENV, "not-an-entry");
+    let result = unsafe { sol_compat_init(0) };
+    env::remove_var(CORE_BPF_PROGRAMS_ENV);
+    assert_eq!(result, 0);
+}
+
+#[cfg(not(feature = "core-bpf"))]
+fn get_transfer_context(cu_avail: u64) -> InstrContext {
+    let native_loader_id = solana_sdk::native_loader::id().to_bytes().to_vec();
+    let account = |address: Vec<u8>, owner: Vec<u8>, executable: bool| AcctState {
+        address,
+        owner,
+        lamports: 1000000,
+        data: vec![],
+        executable,
+        rent_epoch: 0,
+        seed_addr: None,
+    };
+    InstrContext {
+        program_id: solana_config_program::id().to_bytes().to_vec(),
+        accounts: vec![
+            account(vec![1u8; 32], vec![0u8; 32], false),
+            account(vec![2u8; 32], vec![0u8; 32], false),
+            account(vec![0u8; 32], native_loader_id.clone(), true),
+            account(
+                solana_config_program::id().to_bytes().to_vec(),
+                native_loader_id,
+                true,
+            ),
+        ],
+        instr_accounts: vec![
+            InstrAcct {
+                index: 0,
+                is_signer: true,
+                is_writable: true,
+            },
+            InstrAcct {
+                index: 1,
+                is_signer: false,
+                is_writable: true,
+            },
+            InstrAcct {
+                index: 2,
+                is_signer: false,
+                is_writable: false,
+            },
+        ],
+        // Transfer amount of the BPF program, invalid config instruction data
+        data: 10u64.to_be_bytes().to_vec(),
+        cu_avail,
+        ..InstrContext::default()
+    }
+}
+
+#[test]
+#[cfg(not(feature = "core-bpf"))]
+fn test_core_bpf_diff() {
+    // The config program is replaced by a program transferring lamports
+    let elf = std::fs::read(ELF_PATH).unwrap();
+    assert!(set_core_bpf_program(solana_config_program::id(), elf));
+
+    // Both versions exhaust the CU meter
+    let report = execute_instr_core_bpf_diff(get_transfer_context(100)).unwrap();
+    assert!(!report.result_mismatch);
+    assert!(!report.custom_err_mismatch);
+    assert!(report.mismatched_accounts.is_empty());
+    assert!(!report.return_data_mismatch);
+    assert_ne!(report.builtin_effects.unwrap().result, 0);
+
+    // Only the BPF version transfers lamports
+    let report = execute_instr_core_bpf_diff(get_transfer_context(1000000)).unwrap();
+    assert!(report.result_mismatch);
+    assert_eq!(report.bpf_effects.unwrap().result, 0);
+    assert_eq!(
+        report.mismatched_accounts,
+        vec![vec![1u8; 32], vec![2u8; 32]]
+    );
+
+    // A single execution is reconciled with the builtin as well
+    let default_compute_units = solana_config_program::config_processor::DEFAULT_COMPUTE_UNITS;
+    let effects = execute_instr_proto(get_transfer_context(1000000)).unwrap();
+    assert_eq!(effects.result, 0);
+    assert_eq!(effects.cu_avail, 1000000 - default_compute_units);
+    let effects = execute_instr_proto(get_transfer_context(default_compute_units)).unwrap();
+    assert_eq!(
+        effects.result,
+        instr_err_to_num(&InstructionError::ComputationalBudgetExceeded)
+    );
+    assert_eq!(effects.cu_avail, 0);
+
+    clear_core_bpf_program(&solana_config_program::id());
+}
@@ -0,0 +1,98 @@
+use solana_cost_model::block_cost_limits::{SIGNATURE_COST, WRITE_LOCK_UNITS};
+use solana_program::hash::Hash;
+use solana_program::pubkey::Pubkey;
+use solfuzz_agave::pack::execute_pack_cost;
+use solfuzz_agave::proto::{
+    CompiledInstruction, FeatureSet, MessageAddressTableLookup, MessageHeader, PackCostContext,
+    PackCostEffects, SanitizedTransaction, TransactionMessage,
+};
+use solfuzz_agave::HARDCODED_FEATURES;
+
+#[test]
+fn test_pack_cost_transfer() {
+    // System transfer of 1000000 lamports
+    let mut data = vec![2, 0, 0, 0];
+    data.extend_from_slice(&1000000u64.to_le_bytes());
+
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        }),
+        account_keys: vec![
+            Pubkey::new_unique().to_bytes().to_vec(),
+            Pubkey::new_unique().to_bytes().to_vec(),
+            vec![0; 32],
+        ],
+        recent_blockhash: Hash::new_unique().to_bytes().to_vec(),
+        account_shared_data: vec![],
+        instructions: vec![CompiledInstruction {
+            program_id_index: 2,
+            accounts: vec![0, 1],
+            data,
+        }],
+        address_table_lookups: vec![],
+    };
+    let input = PackCostContext {
+        tx: Some(SanitizedTransaction {
+            message: Some(message),
+            message_hash: vec![],
+            is_simple_vote_tx: false,
+            signatures: vec![],
+        }),
+        features: Some(FeatureSet {
+            features: HARDCODED_FEATURES.into(),
+        }),
+    };
+
+    let effects = execute_pack_cost(input).unwrap();
+    assert!(!effects.sanitization_error);
+    assert!(!effects.is_simple_vote);
+    assert_eq!(effects.signature_cost, SIGNATURE_COST);
+    assert_eq!(effects.write_lock_cost, 2 * WRITE_LOCK_UNITS);
+    assert!(effects.programs_execution_cost > 0);
+    assert_eq!(
+        effects.total_cost,
+        effects.signature_cost
+            + effects.write_lock_cost
+            + effects.data_bytes_cost
+            + effects.programs_execution_cost
+            + effects.loaded_accounts_data_size_cost
+    );
+}
+
+#[test]
+fn test_pack_cost_missing_lookup_table() {
+    let message = TransactionMessage {
+        is_legacy: false,
+        header: None,
+        account_keys: vec![Pubkey::new_unique().to_bytes().to_vec()],
+        recent_blockhash: vec![],
+        account_shared_data: vec![],
+        instructions: vec![],
+        address_table_lookups: vec![MessageAddressTableLookup {
+            account_key: Pubkey::new_unique().to_bytes().to_vec(),
+            writable_indexes: vec![0],
+            readonly_indexes: vec![],
+        }],
+    };
+    let input = PackCostContext {
+        tx: Some(SanitizedTransaction {
+            message: Some(message),
+            message_hash: vec![],
+            is_simple_vote_tx: false,
+            signatures: vec![],
+        }),
+        features: None,
+    };
+
+    assert_eq!(
+        execute_pack_cost(input),
+        Some(PackCostEffects {
+            sanitization_error: true,
+            ..Default::default()
+        })
+    );
+}
@@ -4,17 +4,22 @@ use solana_program::bpf_loader_upgradeable::UpgradeableLoaderState;
 use solana_program::hash::Hash;
 use solana_program::pubkey::Pubkey;
 use solana_sdk::clock::Clock;
+use solana_sdk::compute_budget::{self, ComputeBudgetInstruction};
 use solana_sdk::epoch_schedule::EpochSchedule;
 use solana_sdk::rent::Rent;
-use solana_sdk::signature::Signature;
+use solana_sdk::signature::{Signature, Signer};
+use solana_sdk::signer::keypair::keypair_from_seed;
+use solana_sdk::stake_history::StakeHistory;
+use solana_sdk::system_instruction::SystemInstruction;
 use solana_sdk::sysvar::SysvarId;
+use solana_sdk::transaction::TransactionError;
 use solana_sdk::{address_lookup_table, feature_set::*};
 use solfuzz_agave::proto::{
-    AcctState, CompiledInstruction, EpochContext, FeatureSet, MessageHeader, SanitizedTransaction,
-    SlotContext, TransactionMessage, TxnContext, TxnResult,
+    AcctState, CompiledInstruction, EpochContext, FeatureSet, MessageHeader, ProgramDeployment,
+    SanitizedTransaction, SlotContext, TransactionMessage, TxnContext, TxnResult,
 };
-use solfuzz_agave::txn_fuzzer::sol_compat_txn_execute_v1;
-use solfuzz_agave::{feature_list, proto, utils::feature_u64, HARDCODED_FEATURES};
+use solfuzz_agave::txn_fuzzer::{execute_transaction, sign_transaction, sol_compat_txn_execute_v1};
+use solfuzz_agave::{feature_list, proto, utils::feature_u64, Error, HARDCODED_FEATURES};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::{env, fs};
@@ -164,7 +169,12 @@ fn test_txn_execute_clock() {
     let epoch_schedule = get_epoch_schedule_sysvar_account();
     let rent = get_rent_sysvar_account();
 
-    let slot_ctx = SlotContext { slot: 20 };
+    let slot_ctx = SlotContext {
+        slot: Some(20),
+        blockhash: vec![],
+        lamports_per_signature: 0,
+        last_restart_slot: 0,
+    };
     let features = get_features();
     let epoch_ctx = EpochContext {
         features: Some(features),
@@ -191,6 +201,7 @@ fn test_txn_execute_clock() {
 
     let p_acc = std::mem::take(&mut program_info[0].1);
     let pd_acc = std::mem::take(&mut program_info[1].1);
+    let program_size = (p_acc.data.len() + pd_acc.data.len()) as u64;
 
     let instr = CompiledInstruction {
         program_id_index: 1,
@@ -235,6 +246,14 @@ fn test_txn_execute_clock() {
         blockhash_queue: blockhash_queue,
         epoch_ctx: Some(epoch_ctx),
         slot_ctx: Some(slot_ctx),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: true,
+        loaded_accounts_data_size_enabled: true,
+        program_deployments: vec![],
     };
 
     let mut buffer: Vec<u8> = txn_input.encode_to_vec();
@@ -243,7 +262,7 @@ fn test_txn_execute_clock() {
     // Uncomment to write the data to a file
     // write_to_file("clock-test", &buffer);
 
-    let mut res_buffer: Vec<u8> = vec![0; 512];
+    let mut res_buffer: Vec<u8> = vec![0; 4096];
     let mut res_buffer_len = res_buffer.len() as u64;
     let res = unsafe {
         sol_compat_txn_execute_v1(
@@ -259,6 +278,178 @@ fn test_txn_execute_clock() {
     assert!(result.executed);
     assert!(result.is_ok);
     assert_eq!(result.return_data.len(), 8);
+    assert_eq!(
+        result.return_data_program_id,
+        program_info[0].0.to_bytes().to_vec()
+    );
+    assert_eq!(
+        result.logs.first().unwrap(),
+        &format!("Program {} invoke [1]", program_info[0].0)
+    );
+    assert!(result
+        .logs
+        .iter()
+        .any(|log| log.starts_with(&format!("Program return: {}", program_info[0].0))));
+    assert_eq!(
+        result.logs.last().unwrap(),
+        &format!("Program {} success", program_info[0].0)
+    );
+    assert!(result.inner_instructions.is_empty());
+
+    // Logs beyond the limit are replaced by a truncation notice
+    let txn_input = TxnContext {
+        log_messages_bytes_limit: Some(result.logs[0].len() as u64),
+        ..txn_input
+    };
+    let truncated_result = execute_transaction(txn_input).unwrap();
+    assert!(truncated_result.is_ok);
+    assert_eq!(
+        truncated_result.logs,
+        vec![result.logs[0].clone(), "Log truncated".to_string()]
+    );
+
+    // Execution details aren't reported unless the context enables them
+    let txn_input = TxnContext {
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        ..txn_input
+    };
+    let default_result = execute_transaction(txn_input).unwrap();
+    assert!(default_result.is_ok);
+    assert_eq!(default_result.return_data, result.return_data);
+    assert!(default_result.return_data_program_id.is_empty());
+    assert!(default_result.logs.is_empty());
+    assert!(default_result.inner_instructions.is_empty());
+
+    // The program is loaded along with its programdata
+    assert!(!result.loaded_accounts_data_size_estimated);
+    assert_eq!(result.loaded_accounts_data_size_limit, 64 * 1024 * 1024);
+    let program_data_size = result
+        .loaded_account_data_sizes
+        .iter()
+        .find(|account| account.address == program_info[0].0.to_bytes().to_vec())
+        .unwrap();
+    assert_eq!(program_data_size.size, program_size);
+    assert!(result
+        .loaded_account_data_sizes
+        .iter()
+        .any(|account| account.address == bpf_loader_upgradeable::id().to_bytes().to_vec()));
+}
+
+#[test]
+fn test_max_loaded_accounts_data_size_exceeded() {
+    let slot_ctx = SlotContext {
+        slot: Some(20),
+        blockhash: vec![],
+        lamports_per_signature: 0,
+        last_restart_slot: 0,
+    };
+    let epoch_ctx = EpochContext {
+        features: Some(get_features()),
+    };
+
+    let header = MessageHeader {
+        num_required_signatures: 1,
+        num_readonly_signed_accounts: 0,
+        num_readonly_unsigned_accounts: 3,
+    };
+
+    let account = |address: Pubkey, data: Vec<u8>| AcctState {
+        address: address.to_bytes().to_vec(),
+        lamports: 10000000,
+        data,
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+    let fee_payer = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+    let large_account = Pubkey::new_unique();
+
+    let blockhash_queue = vec![
+        Hash::new_unique().to_bytes().to_vec(),
+        Hash::new_unique().to_bytes().to_vec(),
+    ];
+
+    let message = TransactionMessage {
+        is_legacy: false,
+        header: Some(header),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            recipient.to_bytes().to_vec(),
+            large_account.to_bytes().to_vec(),
+            vec![0; 32],
+            compute_budget::id().to_bytes().to_vec(),
+        ],
+        account_shared_data: vec![
+            account(fee_payer, vec![]),
+            account(recipient, vec![]),
+            account(large_account, vec![0; 1024]),
+            get_clock_sysvar_account(),
+            get_epoch_schedule_sysvar_account(),
+            get_rent_sysvar_account(),
+        ],
+        instructions: vec![
+            CompiledInstruction {
+                program_id_index: 4,
+                accounts: vec![],
+                data: ComputeBudgetInstruction::set_loaded_accounts_data_size_limit(1).data,
+            },
+            CompiledInstruction {
+                program_id_index: 3,
+                accounts: vec![0, 1],
+                data: bincode::serialize(&SystemInstruction::Transfer { lamports: 10 }).unwrap(),
+            },
+        ],
+        address_table_lookups: vec![],
+        recent_blockhash: blockhash_queue[1].clone(),
+    };
+
+    let tx = SanitizedTransaction {
+        message: Some(message),
+        message_hash: Hash::new_unique().to_bytes().to_vec(),
+        is_simple_vote_tx: false,
+        signatures: vec![Signature::new_unique().as_ref().to_vec()],
+    };
+
+    let txn_input = TxnContext {
+        tx: Some(tx),
+        blockhash_queue,
+        epoch_ctx: Some(epoch_ctx),
+        slot_ctx: Some(slot_ctx),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: true,
+        program_deployments: vec![],
+    };
+
+    let result = execute_transaction(txn_input).unwrap();
+    assert!(!result.is_ok);
+    let serialized =
+        bincode::serialize(&TransactionError::MaxLoadedAccountsDataSizeExceeded).unwrap();
+    assert_eq!(
+        result.status,
+        u32::from_le_bytes(serialized[0..4].try_into().unwrap()) + 1
+    );
+
+    // Loading failed, so the size is summed from the accounts the loader
+    // would have accounted for
+    assert!(result.loaded_accounts_data_size_estimated);
+    assert_eq!(result.loaded_accounts_data_size_limit, 1);
+    assert!(result.loaded_accounts_data_size >= 1024);
+    assert_eq!(
+        result.loaded_accounts_data_size,
+        result
+            .loaded_account_data_sizes
+            .iter()
+            .map(|account| account.size)
+            .sum::<u64>()
+    );
 }
 
 #[test]
@@ -267,7 +458,12 @@ fn test_simple_transfer() {
     let epoch_schedule = get_epoch_schedule_sysvar_account();
     let rent = get_rent_sysvar_account();
 
-    let slot_ctx = SlotContext { slot: 20 };
+    let slot_ctx = SlotContext {
+        slot: Some(20),
+        blockhash: vec![],
+        lamports_per_signature: 0,
+        last_restart_slot: 0,
+    };
     let features = get_features();
     let epoch_ctx = EpochContext {
         features: Some(features),
@@ -368,6 +564,14 @@ fn test_simple_transfer() {
         blockhash_queue: blockhash_queue,
         epoch_ctx: Some(epoch_ctx),
         slot_ctx: Some(slot_ctx),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
     };
 
     let mut buffer: Vec<u8> = txn_input.encode_to_vec();
@@ -408,7 +612,12 @@ fn test_lookup_table() {
     let epoch_schedule = get_epoch_schedule_sysvar_account();
     let rent = get_rent_sysvar_account();
 
-    let slot_ctx = SlotContext { slot: 20 };
+    let slot_ctx = SlotContext {
+        slot: Some(20),
+        blockhash: vec![],
+        lamports_per_signature: 0,
+        last_restart_slot: 0,
+    };
     let features = get_features();
     let epoch_ctx = EpochContext {
         features: Some(features),
@@ -545,6 +754,14 @@ fn test_lookup_table() {
         blockhash_queue: blockhash_queue,
         epoch_ctx: Some(epoch_ctx),
         slot_ctx: Some(slot_ctx),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
     };
 
     let mut buffer: Vec<u8> = txn_input.encode_to_vec();
@@ -578,3 +795,684 @@ fn test_lookup_table() {
         }
     }
 }
+
+#[test]
+fn test_txn_malformed_inputs() {
+    let fee_payer = Pubkey::new_unique();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 80000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: None,
+        // 31-byte account key
+        account_keys: vec![fee_payer.to_bytes().to_vec(), vec![1; 31]],
+        recent_blockhash: vec![],
+        account_shared_data: vec![fee_payer_data],
+        instructions: vec![],
+        address_table_lookups: vec![],
+    };
+    let tx = SanitizedTransaction {
+        message: Some(message),
+        message_hash: Hash::new_unique().to_bytes().to_vec(),
+        is_simple_vote_tx: false,
+        signatures: vec![Signature::new_unique().as_ref().to_vec()],
+    };
+    let mut txn_input = TxnContext {
+        tx: Some(tx),
+        blockhash_queue: vec![],
+        epoch_ctx: Some(EpochContext {
+            features: Some(get_features()),
+        }),
+        slot_ctx: None,
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
+    };
+
+    let result = execute_transaction(txn_input.clone()).unwrap();
+    assert!(result.sanitization_error);
+    assert!(!result.executed);
+
+    // Malformed contexts are rejected
+    txn_input.blockhash_queue = vec![vec![0; 31]];
+    assert_eq!(execute_transaction(txn_input), None);
+}
+
+#[test]
+fn test_txn_fees_only() {
+    let slot_ctx = SlotContext {
+        slot: Some(20),
+        blockhash: vec![],
+        lamports_per_signature: 0,
+        last_restart_slot: 0,
+    };
+    let mut features = get_features();
+    features
+        .features
+        .extend_from_slice(feature_list![enable_transaction_loading_failure_fees]);
+    let epoch_ctx = EpochContext {
+        features: Some(features),
+    };
+
+    let header = MessageHeader {
+        num_required_signatures: 1,
+        num_readonly_signed_accounts: 0,
+        num_readonly_unsigned_accounts: 1,
+    };
+
+    let fee_payer = Pubkey::new_unique();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 10000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+
+    // The program account doesn't exist, so loading fails after the fee
+    // payer has been charged
+    let missing_program = Pubkey::new_unique();
+    let instr = CompiledInstruction {
+        program_id_index: 1,
+        accounts: vec![],
+        data: vec![],
+    };
+
+    let blockhash_queue = vec![Hash::new_unique().to_bytes().to_vec()];
+
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(header),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            missing_program.to_bytes().to_vec(),
+        ],
+        recent_blockhash: blockhash_queue[0].clone(),
+        account_shared_data: vec![
+            fee_payer_data,
+            get_clock_sysvar_account(),
+            get_epoch_schedule_sysvar_account(),
+            get_rent_sysvar_account(),
+        ],
+        instructions: vec![instr],
+        address_table_lookups: vec![],
+    };
+
+    let tx = SanitizedTransaction {
+        message: Some(message),
+        message_hash: Hash::new_unique().to_bytes().to_vec(),
+        is_simple_vote_tx: false,
+        signatures: vec![Signature::new_unique().as_ref().to_vec()],
+    };
+
+    let txn_input = TxnContext {
+        tx: Some(tx),
+        blockhash_queue,
+        epoch_ctx: Some(epoch_ctx),
+        slot_ctx: Some(slot_ctx),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
+    };
+
+    let result = execute_transaction(txn_input).unwrap();
+    assert!(result.executed);
+    assert!(!result.is_ok);
+    assert_eq!(result.executed_units, 0);
+
+    let fees = result.fee_details.unwrap();
+    let state = result.resulting_state.unwrap();
+    assert_eq!(state.acct_states.len(), 1);
+    assert_eq!(state.acct_states[0].address, fee_payer.to_bytes().to_vec());
+    assert_eq!(
+        state.acct_states[0].lamports,
+        10000000 - fees.transaction_fee - fees.prioritization_fee
+    );
+}
+
+#[test]
+fn test_txn_epoch_boundary() {
+    let slot_ctx = SlotContext {
+        slot: Some(20),
+        blockhash: vec![],
+        lamports_per_signature: 0,
+        last_restart_slot: 0,
+    };
+    let epoch_ctx = EpochContext {
+        features: Some(get_features()),
+    };
+
+    let header = MessageHeader {
+        num_required_signatures: 1,
+        num_readonly_signed_accounts: 0,
+        num_readonly_unsigned_accounts: 1,
+    };
+
+    let fee_payer = Pubkey::new_unique();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 10000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+    let recipient = Pubkey::new_unique();
+
+    // System transfer of 1000000 lamports
+    let mut data = vec![2, 0, 0, 0];
+    data.extend_from_slice(&1000000u64.to_le_bytes());
+    let instr = CompiledInstruction {
+        program_id_index: 2,
+        accounts: vec![0, 1],
+        data,
+    };
+
+    let blockhash_queue = vec![Hash::new_unique().to_bytes().to_vec()];
+
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(header),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            recipient.to_bytes().to_vec(),
+            vec![0; 32],
+        ],
+        recent_blockhash: blockhash_queue[0].clone(),
+        account_shared_data: vec![
+            fee_payer_data,
+            get_epoch_schedule_sysvar_account(),
+            get_rent_sysvar_account(),
+        ],
+        instructions: vec![instr],
+        address_table_lookups: vec![],
+    };
+
+    let tx = SanitizedTransaction {
+        message: Some(message),
+        message_hash: Hash::new_unique().to_bytes().to_vec(),
+        is_simple_vote_tx: false,
+        signatures: vec![Signature::new_unique().as_ref().to_vec()],
+    };
+
+    let txn_input = TxnContext {
+        tx: Some(tx),
+        blockhash_queue,
+        epoch_ctx: Some(epoch_ctx),
+        slot_ctx: Some(slot_ctx),
+        epoch_boundary: true,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
+    };
+
+    let result = execute_transaction(txn_input).unwrap();
+    assert!(result.executed);
+    assert!(result.is_ok);
+
+    // The transaction executes in the first slot of epoch 1
+    let clock_account = result
+        .epoch_boundary_accounts
+        .iter()
+        .find(|account| account.address == Clock::id().to_bytes().to_vec())
+        .unwrap();
+    let clock: Clock = bincode::deserialize(&clock_account.data).unwrap();
+    assert_eq!(clock.slot, 32);
+    assert_eq!(clock.epoch, 1);
+    assert!(result
+        .epoch_boundary_accounts
+        .iter()
+        .any(|account| account.address == StakeHistory::id().to_bytes().to_vec()));
+}
+
+#[test]
+fn test_txn_verify_signatures() {
+    let seed = [7u8; 32];
+    let fee_payer = keypair_from_seed(&seed).unwrap().pubkey();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 10000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+    let recipient = Pubkey::new_unique();
+
+    // System transfer of 1000000 lamports
+    let mut data = vec![2, 0, 0, 0];
+    data.extend_from_slice(&1000000u64.to_le_bytes());
+    let instr = CompiledInstruction {
+        program_id_index: 2,
+        accounts: vec![0, 1],
+        data,
+    };
+
+    let blockhash_queue = vec![Hash::new_unique().to_bytes().to_vec()];
+
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        }),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            recipient.to_bytes().to_vec(),
+            vec![0; 32],
+        ],
+        recent_blockhash: blockhash_queue[0].clone(),
+        account_shared_data: vec![fee_payer_data],
+        instructions: vec![instr],
+        address_table_lookups: vec![],
+    };
+
+    let mut txn_input = TxnContext {
+        tx: Some(SanitizedTransaction {
+            message: Some(message),
+            message_hash: vec![],
+            is_simple_vote_tx: false,
+            signatures: vec![],
+        }),
+        blockhash_queue,
+        epoch_ctx: Some(EpochContext {
+            features: Some(get_features()),
+        }),
+        slot_ctx: None,
+        epoch_boundary: false,
+        verify_signatures: true,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
+    };
+
+    // Missing signer keypair
+    assert_eq!(
+        sign_transaction(&mut txn_input, &[[8u8; 32]]),
+        Err(Error::AccountMissing)
+    );
+
+    // Unsigned
+    let result = execute_transaction(txn_input.clone()).unwrap();
+    assert!(result.sanitization_error);
+
+    sign_transaction(&mut txn_input, &[seed]).unwrap();
+    let result = execute_transaction(txn_input.clone()).unwrap();
+    assert!(result.executed);
+    assert!(result.is_ok);
+
+    // Tampered signature
+    txn_input.tx.as_mut().unwrap().signatures[0][0] ^= 1;
+    let result = execute_transaction(txn_input.clone()).unwrap();
+    assert!(result.sanitization_error);
+    assert!(!result.executed);
+
+    // Signatures aren't checked unless requested
+    txn_input.verify_signatures = false;
+    let result = execute_transaction(txn_input).unwrap();
+    assert!(result.is_ok);
+}
+
+#[test]
+fn test_txn_template_bank_isolation() {
+    let fee_payer = Pubkey::new_unique();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 10000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+    let recipient = Pubkey::new_unique();
+
+    // System transfer of 1000000 lamports
+    let mut data = vec![2, 0, 0, 0];
+    data.extend_from_slice(&1000000u64.to_le_bytes());
+    let instr = CompiledInstruction {
+        program_id_index: 2,
+        accounts: vec![0, 1],
+        data,
+    };
+
+    let blockhash_queue = vec![Hash::new_unique().to_bytes().to_vec()];
+
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        }),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            recipient.to_bytes().to_vec(),
+            vec![0; 32],
+        ],
+        recent_blockhash: blockhash_queue[0].clone(),
+        account_shared_data: vec![fee_payer_data],
+        instructions: vec![instr],
+        address_table_lookups: vec![],
+    };
+
+    let mut txn_input = TxnContext {
+        tx: Some(SanitizedTransaction {
+            message: Some(message),
+            message_hash: Hash::new_unique().to_bytes().to_vec(),
+            is_simple_vote_tx: false,
+            signatures: vec![Signature::new_unique().as_ref().to_vec()],
+        }),
+        blockhash_queue,
+        epoch_ctx: Some(EpochContext {
+            features: Some(get_features()),
+        }),
+        slot_ctx: Some(SlotContext {
+            slot: Some(20),
+            blockhash: vec![],
+            lamports_per_signature: 0,
+            last_restart_slot: 0,
+        }),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
+    };
+
+    // Repeated executions share the same genesis bank, and observe the same
+    // state
+    let result = execute_transaction(txn_input.clone()).unwrap();
+    assert!(result.is_ok);
+    assert_eq!(execute_transaction(txn_input.clone()), Some(result));
+
+    // A fixture with an account for the system program doesn't evict the
+    // builtin from the program cache of later fixtures
+    let mut system_program_input = txn_input.clone();
+    system_program_input
+        .tx
+        .as_mut()
+        .unwrap()
+        .message
+        .as_mut()
+        .unwrap()
+        .account_shared_data
+        .push(AcctState {
+            address: vec![0; 32],
+            lamports: 1,
+            data: b"system_program".to_vec(),
+            executable: true,
+            rent_epoch: 0,
+            owner: solana_sdk::native_loader::id().to_bytes().to_vec(),
+            seed_addr: None,
+        });
+    for _ in 0..2 {
+        let result = execute_transaction(system_program_input.clone()).unwrap();
+        assert!(result.is_ok);
+    }
+    assert!(execute_transaction(txn_input.clone()).unwrap().is_ok);
+
+    // The fee payer stored by the previous executions isn't visible anymore
+    txn_input
+        .tx
+        .as_mut()
+        .unwrap()
+        .message
+        .as_mut()
+        .unwrap()
+        .account_shared_data
+        .clear();
+    let result = execute_transaction(txn_input).unwrap();
+    assert!(!result.executed);
+    assert!(!result.is_ok);
+}
+
+#[test]
+fn test_txn_program_deployment_slots() {
+    let fee_payer = Pubkey::new_unique();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 80000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+
+    let mut program_info = deploy_program("clock-sysvar".to_string());
+    let program_id = program_info[0].0;
+    let p_acc = std::mem::take(&mut program_info[0].1);
+    let mut pd_acc = std::mem::take(&mut program_info[1].1);
+
+    // The program was upgraded at slot 15
+    let state = UpgradeableLoaderState::ProgramData {
+        slot: 15,
+        upgrade_authority_address: None,
+    };
+    let state = bincode::serialize(&state).unwrap();
+    pd_acc.data[..state.len()].copy_from_slice(&state);
+
+    let blockhash_queue = vec![Hash::new_unique().to_bytes().to_vec()];
+
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 0,
+        }),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            program_id.to_bytes().to_vec(),
+        ],
+        recent_blockhash: blockhash_queue[0].clone(),
+        account_shared_data: vec![
+            fee_payer_data,
+            p_acc,
+            pd_acc,
+            get_clock_sysvar_account(),
+            get_epoch_schedule_sysvar_account(),
+            get_rent_sysvar_account(),
+        ],
+        instructions: vec![CompiledInstruction {
+            program_id_index: 1,
+            accounts: vec![],
+            data: vec![],
+        }],
+        address_table_lookups: vec![],
+    };
+
+    // The version deployed at slot 10 doesn't verify
+    let mut txn_input = TxnContext {
+        tx: Some(SanitizedTransaction {
+            message: Some(message),
+            message_hash: Hash::new_unique().to_bytes().to_vec(),
+            is_simple_vote_tx: false,
+            signatures: vec![Signature::new_unique().as_ref().to_vec()],
+        }),
+        blockhash_queue,
+        epoch_ctx: Some(EpochContext {
+            features: Some(get_features()),
+        }),
+        slot_ctx: Some(SlotContext {
+            slot: Some(20),
+            blockhash: vec![],
+            lamports_per_signature: 0,
+            last_restart_slot: 0,
+        }),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: false,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![ProgramDeployment {
+            program_id: program_id.to_bytes().to_vec(),
+            slot: 10,
+            elf: vec![0; 16],
+        }],
+    };
+
+    // Without the modification slot check, the stale cache entry is used
+    let result = execute_transaction(txn_input.clone()).unwrap();
+    assert!(!result.is_ok);
+
+    // The deployment isn't visible to later fixtures, which load the program
+    // from its accounts
+    let undeployed_input = TxnContext {
+        program_deployments: vec![],
+        ..txn_input.clone()
+    };
+    let result = execute_transaction(undeployed_input).unwrap();
+    assert!(result.is_ok);
+
+    // With it, the upgraded program is loaded from its accounts
+    txn_input.check_program_modification_slot = true;
+    let result = execute_transaction(txn_input.clone()).unwrap();
+    assert!(result.is_ok);
+    assert_eq!(result.return_data.len(), 8);
+
+    // Deployments can't be in the future
+    txn_input.program_deployments[0].slot = 21;
+    assert_eq!(execute_transaction(txn_input), None);
+}
+
+#[test]
+fn test_txn_extended_resulting_state() {
+    let fee_payer = Pubkey::new_unique();
+    let fee_payer_data = AcctState {
+        address: fee_payer.to_bytes().to_vec(),
+        lamports: 80000000,
+        data: vec![],
+        executable: false,
+        rent_epoch: 0,
+        owner: vec![0; 32],
+        seed_addr: None,
+    };
+
+    let mut program_info = deploy_program("clock-sysvar".to_string());
+    let p_acc = std::mem::take(&mut program_info[0].1);
+    let pd_acc = std::mem::take(&mut program_info[1].1);
+
+    let blockhash_queue = vec![Hash::new_unique().to_bytes().to_vec()];
+
+    // The clock sysvar is requested as writable, but is a reserved key
+    let message = TransactionMessage {
+        is_legacy: true,
+        header: Some(MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        }),
+        account_keys: vec![
+            fee_payer.to_bytes().to_vec(),
+            Clock::id().to_bytes().to_vec(),
+            program_info[0].0.to_bytes().to_vec(),
+        ],
+        recent_blockhash: blockhash_queue[0].clone(),
+        account_shared_data: vec![
+            fee_payer_data,
+            p_acc,
+            pd_acc,
+            get_clock_sysvar_account(),
+            get_epoch_schedule_sysvar_account(),
+            get_rent_sysvar_account(),
+        ],
+        instructions: vec![CompiledInstruction {
+            program_id_index: 2,
+            accounts: vec![],
+            data: vec![],
+        }],
+        address_table_lookups: vec![],
+    };
+
+    let txn_input = TxnContext {
+        tx: Some(SanitizedTransaction {
+            message: Some(message),
+            message_hash: Hash::new_unique().to_bytes().to_vec(),
+            is_simple_vote_tx: false,
+            signatures: vec![Signature::new_unique().as_ref().to_vec()],
+        }),
+        blockhash_queue,
+        epoch_ctx: Some(EpochContext {
+            features: Some(get_features()),
+        }),
+        slot_ctx: Some(SlotContext {
+            slot: Some(20),
+            blockhash: vec![],
+            lamports_per_signature: 0,
+            last_restart_slot: 0,
+        }),
+        epoch_boundary: false,
+        verify_signatures: false,
+        check_program_modification_slot: false,
+        extended_resulting_state: true,
+        log_messages_bytes_limit: None,
+        execution_details_enabled: false,
+        loaded_accounts_data_size_enabled: false,
+        program_deployments: vec![],
+    };
+
+    let result = execute_transaction(txn_input).unwrap();
+    assert!(result.is_ok);
+    let state = result.resulting_state.unwrap();
+
+    // Only the fee payer is writable after demotion
+    assert_eq!(state.acct_states.len(), 1);
+
+    let flags = state
+        .loaded_accounts
+        .iter()
+        .map(|account| {
+            (
+                account.state.as_ref().unwrap().address.clone(),
+                account.is_writable_requested,
+                account.is_writable,
+                account.is_signer,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        flags,
+        vec![
+            (fee_payer.to_bytes().to_vec(), true, true, true),
+            (Clock::id().to_bytes().to_vec(), true, false, false),
+            (program_info[0].0.to_bytes().to_vec(), false, false, false),
+            (program_info[1].0.to_bytes().to_vec(), false, false, false),
+        ]
+    );
+}
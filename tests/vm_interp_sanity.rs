@@ -0,0 +1,201 @@
+use solana_compute_budget::compute_budget::ComputeBudget;
+use solana_program_runtime::solana_rbpf::ebpf::hash_symbol_name;
+use solfuzz_agave::proto::{InstrContext, SyscallContext, SyscallInvocation, VmContext};
+use solfuzz_agave::vm_interp::{execute_vm_interp, execute_vm_interp_jit_diff};
+
+fn get_syscall_context(text: Vec<u8>) -> SyscallContext {
+    SyscallContext {
+        vm_ctx: Some(VmContext {
+            rodata: text,
+            entry_pc: 0,
+            heap_max: 0,
+            ..VmContext::default()
+        }),
+        instr_ctx: Some(InstrContext {
+            program_id: vec![0; 32],
+            cu_avail: 100,
+            ..InstrContext::default()
+        }),
+        syscall_invocation: Some(SyscallInvocation::default()),
+        ..SyscallContext::default()
+    }
+}
+
+#[test]
+fn test_vm_interp_jit_diff() {
+    let text = vec![
+        0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+
+    let effects = execute_vm_interp(get_syscall_context(text.clone())).unwrap();
+    assert_eq!(effects.error, 0);
+    assert_eq!(effects.r0, 42);
+
+    let report = execute_vm_interp_jit_diff(get_syscall_context(text)).unwrap();
+    assert!(!report.r0_mismatch);
+    assert!(!report.error_mismatch);
+    assert!(!report.pc_mismatch);
+    assert!(!report.cu_avail_mismatch);
+    assert!(!report.frame_count_mismatch);
+    assert!(!report.heap_mismatch);
+    assert!(!report.stack_mismatch);
+    assert!(!report.input_data_regions_mismatch);
+    assert_eq!(report.interpreter_effects, Some(effects.clone()));
+    assert_eq!(report.jit_effects, Some(effects));
+
+    // CUs left by faulting programs aren't compared
+    let text = vec![
+        0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+        0x79, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ldxdw r2, [r0+0]
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    let report = execute_vm_interp_jit_diff(get_syscall_context(text)).unwrap();
+    assert_ne!(report.interpreter_effects.unwrap().error, 0);
+    assert!(!report.error_mismatch);
+    assert!(!report.cu_avail_mismatch);
+}
+
+#[test]
+fn test_vm_interp_trace() {
+    let text = vec![
+        0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+
+    let effects = execute_vm_interp(get_syscall_context(text.clone())).unwrap();
+    assert!(effects.trace.is_empty());
+
+    let mut context = get_syscall_context(text);
+    context.max_trace_len = 1;
+    let effects = execute_vm_interp(context).unwrap();
+    assert_eq!(effects.r0, 42);
+    assert_eq!(effects.trace.len(), 1);
+    assert_eq!(effects.trace[0].pc, 0);
+    assert_eq!(effects.trace[0].opcode, 0xb7);
+    assert_eq!(effects.trace[0].registers.len(), 12);
+    assert_eq!(effects.trace[0].cu_avail, Some(100));
+}
+
+#[test]
+fn test_vm_interp_sbpf_version() {
+    let text = vec![
+        0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    let lddw_text = vec![
+        0x18, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // lddw r0, 42
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    let with_version = |text: Vec<u8>, sbpf_version: u32| {
+        let mut context = get_syscall_context(text);
+        context.vm_ctx.as_mut().unwrap().sbpf_version = sbpf_version;
+        context
+    };
+
+    let effects = execute_vm_interp(with_version(text.clone(), 2)).unwrap();
+    assert_eq!(effects.error, 0);
+    assert_eq!(effects.r0, 42);
+
+    // lddw is still valid in V1 but was removed in V2
+    let effects = execute_vm_interp(with_version(lddw_text.clone(), 1)).unwrap();
+    assert_eq!(effects.error, 0);
+    assert_eq!(effects.r0, 42);
+    let effects = execute_vm_interp(with_version(lddw_text, 2)).unwrap();
+    assert_eq!(effects.error, -1);
+
+    assert!(execute_vm_interp(with_version(text, u32::MAX)).is_none());
+}
+
+#[test]
+fn test_vm_interp_real_syscalls() {
+    let mut text = vec![0x85, 0x00, 0x00, 0x00]; // call sol_log_64_
+    text.extend_from_slice(&hash_symbol_name(b"sol_log_64_").to_le_bytes());
+    text.extend_from_slice(&[
+        0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ]);
+
+    let mut context = get_syscall_context(text);
+    context.instr_ctx.as_mut().unwrap().cu_avail = 1000;
+    let stubbed_effects = execute_vm_interp(context.clone()).unwrap();
+    assert_eq!(stubbed_effects.error, 0);
+    assert!(stubbed_effects.log.is_empty());
+
+    // Stubbed syscalls consume no CUs, so the difference is the syscall's cost
+    context.real_syscalls = true;
+    context.max_trace_len = 3;
+    let effects = execute_vm_interp(context).unwrap();
+    assert_eq!(effects.error, 0);
+    assert_eq!(effects.r0, 42);
+    assert_eq!(
+        stubbed_effects.cu_avail - effects.cu_avail,
+        ComputeBudget::default().log_64_units
+    );
+    assert!(!effects.trace.is_empty());
+    assert!(effects.trace.iter().all(|entry| entry.cu_avail.is_none()));
+    assert!(String::from_utf8(effects.log)
+        .unwrap()
+        .starts_with("Program log: "));
+}
+
+#[test]
+fn test_vm_interp_text_section() {
+    let mut rodata = 42u64.to_le_bytes().to_vec();
+    rodata.extend_from_slice(&[
+        0x18, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // lddw r1, 0x100000000
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, //
+        0x79, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ldxdw r0, [r1+0]
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ]);
+
+    let mut context = get_syscall_context(rodata.clone());
+    let vm_ctx = context.vm_ctx.as_mut().unwrap();
+    vm_ctx.rodata_text_section_offset = 8;
+    vm_ctx.rodata_text_section_length = 32;
+    let effects = execute_vm_interp(context.clone()).unwrap();
+    assert_eq!(effects.error, 0);
+    assert_eq!(effects.r0, 42);
+    assert_eq!(effects.rodata, rodata);
+
+    context.vm_ctx.as_mut().unwrap().rodata_text_section_length = 40;
+    assert!(execute_vm_interp(context).is_none());
+}
+
+#[test]
+fn test_vm_interp_text_section_callx() {
+    let text = vec![
+        0x18, 0x01, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, // lddw r1, 0x100000020
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, //
+        0x8d, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // callx r1
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+        0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+
+    let mut context = get_syscall_context(text.clone());
+    context.vm_ctx.as_mut().unwrap().rodata_text_section_length = text.len() as u64;
+    let effects = execute_vm_interp(context).unwrap();
+    assert_eq!(effects.error, 0);
+    assert_eq!(effects.r0, 42);
+
+    // callx targets are vaddrs, which account for the text section's offset
+    let mut rodata = vec![0; 8];
+    rodata.extend_from_slice(&text);
+    rodata[8 + 4] = 0x28; // lddw r1, 0x100000028
+    let mut context = get_syscall_context(rodata.clone());
+    let vm_ctx = context.vm_ctx.as_mut().unwrap();
+    vm_ctx.rodata_text_section_offset = 8;
+    vm_ctx.rodata_text_section_length = text.len() as u64;
+    let effects = execute_vm_interp(context.clone()).unwrap();
+    assert_eq!(effects.error, 0);
+    assert_eq!(effects.r0, 42);
+
+    // Targets in rodata before the text section are outside of it
+    rodata[8 + 4] = 0x00; // lddw r1, 0x100000000
+    context.vm_ctx.as_mut().unwrap().rodata = rodata;
+    let effects = execute_vm_interp(context).unwrap();
+    assert_eq!(effects.error, 8); // FD_VM_ERR_SIGTEXT
+    assert_eq!(effects.pc, 2);
+}